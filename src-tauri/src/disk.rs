@@ -0,0 +1,374 @@
+//! Files on disk referenced by <file><name>: collecting files under folders,
+//! and converting between disk paths and names relative to the project XML.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// Files chosen by the user: plain files as given, folders walked recursively
+/// (in file-name order) keeping the files whose path relative to the folder
+/// matches one of `patterns` (all files when `patterns` is empty)
+pub(crate) fn collect_files(paths: &[String], patterns: &[String]) -> Result<Vec<PathBuf>, String> {
+    let patterns = compile_globs(patterns)?;
+    let mut out = Vec::new();
+    for p in paths {
+        let path = Path::new(p);
+        if !path.is_dir() {
+            out.push(path.to_path_buf());
+            continue;
+        }
+        for entry in walkdir::WalkDir::new(path).sort_by_file_name() {
+            let entry = entry.map_err(|e| format!("read dir error: {}", e))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let rel = entry.path().strip_prefix(path).unwrap_or(entry.path());
+            if matches_any(&patterns, rel) {
+                out.push(entry.into_path());
+            }
+        }
+    }
+    Ok(out)
+}
+
+pub(crate) fn compile_globs(patterns: &[String]) -> Result<Vec<glob::Pattern>, String> {
+    patterns
+        .iter()
+        .map(|p| glob::Pattern::new(p).map_err(|e| format!("invalid glob `{}`: {}", p, e)))
+        .collect()
+}
+
+/// True when `rel` matches one of `patterns` (or there are none). Patterns without
+/// a separator ("*.c") are matched against the file name, others against the path.
+pub(crate) fn matches_any(patterns: &[glob::Pattern], rel: &Path) -> bool {
+    if patterns.is_empty() {
+        return true;
+    }
+    let rel_str = rel.to_string_lossy().replace('\\', "/");
+    let file_name = rel
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    patterns.iter().any(|p| {
+        if p.as_str().contains('/') {
+            p.matches(&rel_str)
+        } else {
+            p.matches(&file_name)
+        }
+    })
+}
+
+/// Folders not listed in the include tree unless other exclude globs are given
+pub(crate) const DEFAULT_FOLDER_EXCLUDES: [&str; 3] = [".git", "node_modules", "target"];
+
+/// Lists subfolders for the include tree, leaving out folders matching one of
+/// the exclude globs (relative to `root`, as in `matches_any`) and ignored ones
+pub(crate) struct FolderScan {
+    root: PathBuf,
+    exclude: Vec<glob::Pattern>,
+    /// ignored folders; their contents are never listed, so only they are needed
+    ignored: Arc<HashSet<PathBuf>>,
+}
+
+impl FolderScan {
+    pub(crate) fn new(
+        root: &Path,
+        exclude: &[String],
+        ignored: Arc<HashSet<PathBuf>>,
+    ) -> Result<Self, String> {
+        Ok(FolderScan {
+            root: normalize(root),
+            exclude: compile_globs(exclude)?,
+            ignored,
+        })
+    }
+
+    pub(crate) fn is_excluded(&self, dir: &Path) -> bool {
+        if self.ignored.contains(dir) {
+            return true;
+        }
+        let rel = dir.strip_prefix(&self.root).unwrap_or(dir);
+        !self.exclude.is_empty() && matches_any(&self.exclude, rel)
+    }
+
+    /// Subfolders of `dir` that are not excluded, in name order
+    pub(crate) fn subfolders(&self, dir: &Path) -> Vec<PathBuf> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut out: Vec<PathBuf> = entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_dir() && !self.is_excluded(p))
+            .collect();
+        out.sort();
+        out
+    }
+}
+
+/// Absolute path with `.` and `..` removed, without touching the filesystem
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let abs = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut out = PathBuf::new();
+    for c in abs.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `target` relative to the directory `base` ("src/a.c", "../lib/b.c").
+/// None when there is no relative path (e.g. another drive on Windows).
+pub(crate) fn relative_path(base: &Path, target: &Path) -> Option<PathBuf> {
+    let base = normalize(base);
+    let target = normalize(target);
+    let (mut b, mut t) = (base.components().peekable(), target.components().peekable());
+    // the root / drive prefix has to be the same
+    if b.peek() != t.peek() {
+        return None;
+    }
+    while let (Some(x), Some(y)) = (b.peek(), t.peek()) {
+        if x != y {
+            break;
+        }
+        b.next();
+        t.next();
+    }
+    let mut rel = PathBuf::new();
+    for _ in b {
+        rel.push("..");
+    }
+    for c in t {
+        rel.push(c);
+    }
+    Some(rel)
+}
+
+/// Separator used by existing <file><name> values: '\' when names only use
+/// backslashes, '/' when any uses slashes, the platform's separator when no
+/// name contains a separator
+pub(crate) fn detect_separator<'a>(names: impl Iterator<Item = &'a str>) -> char {
+    let (mut slash, mut backslash) = (false, false);
+    for name in names {
+        slash |= name.contains('/');
+        backslash |= name.contains('\\');
+    }
+    match (slash, backslash) {
+        (true, _) => '/',
+        (false, true) => '\\',
+        (false, false) => std::path::MAIN_SEPARATOR,
+    }
+}
+
+/// Relative path as a <name> value with the given separator
+pub(crate) fn to_name(path: &Path, sep: char) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(&sep.to_string())
+}
+
+/// <name> value for a file on disk: relative to `base` with separator `sep`, or
+/// the absolute path when there is no relative path. The flag is true when the
+/// file lies outside `base`.
+pub(crate) fn name_for(base: &Path, file: &Path, sep: char) -> (String, bool) {
+    match relative_path(base, file) {
+        Some(rel) => (to_name(&rel, sep), rel.starts_with("..")),
+        None => (normalize(file).to_string_lossy().into_owned(), true),
+    }
+}
+
+/// A <name> value (either separator) joined to `base`
+pub(crate) fn resolve_name(base: &Path, name: &str) -> PathBuf {
+    let native = name
+        .trim()
+        .replace(['/', '\\'], std::path::MAIN_SEPARATOR_STR);
+    normalize(&base.join(native))
+}
+
+/// Expand `$(Name)` macros in an include directory value: `$(ProjectDir)` (the
+/// project file's folder with a trailing separator, as in MSBuild) and environment
+/// variables. Macro names are case-insensitive; unknown macros are left as written.
+pub(crate) fn expand_macros(value: &str, project_dir: &Path) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("$(") {
+        let Some(len) = rest[start + 2..].find(')') else {
+            break;
+        };
+        let end = start + 2 + len + 1;
+        out.push_str(&rest[..start]);
+        match macro_value(&rest[start + 2..end - 1], project_dir) {
+            Some(v) => out.push_str(&v),
+            None => out.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn macro_value(name: &str, project_dir: &Path) -> Option<String> {
+    if name.eq_ignore_ascii_case("ProjectDir") {
+        let mut dir = project_dir.to_string_lossy().into_owned();
+        if !dir.ends_with(std::path::MAIN_SEPARATOR) {
+            dir.push(std::path::MAIN_SEPARATOR);
+        }
+        return Some(dir);
+    }
+    std::env::vars()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v)
+}
+
+/// Name comparison key: separators unified, "./" prefix dropped
+pub(crate) fn name_key(name: &str) -> String {
+    let unified = name.trim().replace('\\', "/");
+    unified.strip_prefix("./").unwrap_or(&unified).to_string()
+}
+
+/// State of a <file> entry on disk
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FileStatus {
+    Ok,
+    Missing,
+    /// exists, but spelled with different case than on disk
+    CaseMismatch,
+    /// another entry of the same group refers to the same file on disk
+    DuplicateOnDisk,
+}
+
+pub(crate) struct Resolved {
+    /// the name joined to the project directory, as written
+    pub(crate) path: PathBuf,
+    /// the same path spelled as on disk; None when the file does not exist
+    pub(crate) actual: Option<PathBuf>,
+    pub(crate) case_mismatch: bool,
+}
+
+/// Resolves <name> values relative to the project directory, looking up each
+/// path component in its directory listing so that case differences are found
+/// on case-insensitive file systems too. Listings are cached per Resolver.
+pub(crate) struct Resolver {
+    base: PathBuf,
+    listings: HashMap<PathBuf, Option<Vec<String>>>,
+}
+
+impl Resolver {
+    pub(crate) fn new(base: &Path) -> Self {
+        Resolver {
+            base: normalize(base),
+            listings: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn resolve(&mut self, name: &str) -> Resolved {
+        let path = resolve_name(&self.base, name);
+        let mut actual = PathBuf::new();
+        let mut case_mismatch = false;
+        for c in path.components() {
+            let Component::Normal(part) = c else {
+                actual.push(c);
+                continue;
+            };
+            let part = part.to_string_lossy();
+            let Some(entries) = self.listing(&actual) else {
+                return Resolved {
+                    path,
+                    actual: None,
+                    case_mismatch,
+                };
+            };
+            let found = match entries.iter().find(|e| **e == part) {
+                Some(e) => Some(e.clone()),
+                None => {
+                    let lower = part.to_lowercase();
+                    let e = entries.iter().find(|e| e.to_lowercase() == lower).cloned();
+                    case_mismatch |= e.is_some();
+                    e
+                }
+            };
+            match found {
+                Some(e) => actual.push(e),
+                None => {
+                    return Resolved {
+                        path,
+                        actual: None,
+                        case_mismatch,
+                    }
+                }
+            }
+        }
+        Resolved {
+            path,
+            actual: Some(actual),
+            case_mismatch,
+        }
+    }
+
+    fn listing(&mut self, dir: &Path) -> Option<&Vec<String>> {
+        self.listings
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let entries = std::fs::read_dir(dir).ok()?;
+                Some(
+                    entries
+                        .flatten()
+                        .map(|e| e.file_name().to_string_lossy().into_owned())
+                        .collect(),
+                )
+            })
+            .as_ref()
+    }
+}
+
+/// Resolves include directory values (as written in a project) to one path per
+/// folder: macros expanded, `.`/`..` and either separator handled. Windows paths
+/// (written with backslashes, or any path on Windows) are case-insensitive: they
+/// are spelled as on disk and match other folders ignoring case, so that `Inc\A`
+/// and `inc/a` meet. Other paths only name the folder spelled exactly that way;
+/// the first spelling wins.
+pub(crate) struct IncludeResolver {
+    project_dir: PathBuf,
+    resolver: Resolver,
+    /// folders seen so far, flagged when written Windows-style
+    seen: Vec<(PathBuf, bool)>,
+}
+
+impl IncludeResolver {
+    pub(crate) fn new(project_dir: &Path) -> Self {
+        IncludeResolver {
+            project_dir: normalize(project_dir),
+            resolver: Resolver::new(project_dir),
+            seen: Vec::new(),
+        }
+    }
+
+    pub(crate) fn resolve(&mut self, value: &str) -> PathBuf {
+        let expanded = expand_macros(value.trim(), &self.project_dir);
+        let windows = cfg!(windows) || expanded.contains('\\');
+        let resolved = self.resolver.resolve(&expanded);
+        let path = match resolved.actual {
+            Some(actual) if windows || !resolved.case_mismatch => actual,
+            _ => resolved.path,
+        };
+        let key = path.to_string_lossy().to_lowercase();
+        let seen = self.seen.iter().find(|(p, w)| {
+            *p == path || ((windows || *w) && p.to_string_lossy().to_lowercase() == key)
+        });
+        match seen {
+            Some((p, _)) => p.clone(),
+            None => {
+                self.seen.push((path.clone(), windows));
+                path
+            }
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Duplicate file / group name detection for find_duplicates and dedupe
+
+use crate::{get_child_text, node_id_of, tree_children, NODE_ID_ATTR};
+use serde::Serialize;
+use std::collections::HashMap;
+use xmltree::{Element, XMLNode};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DuplicateScope {
+    /// same name among the direct children of one group
+    Group,
+    /// same file name in different groups of one target / group name among a target's groups
+    Target,
+    /// same file name in different targets of the project
+    AcrossTargets,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct Duplicate {
+    /// "file" or "group"
+    pub(crate) kind: String,
+    pub(crate) scope: DuplicateScope,
+    pub(crate) name: String,
+    /// node id of the group / target / project the duplicates were found in
+    pub(crate) container_id: String,
+    /// every occurrence in document order (the first one is kept by dedupe)
+    pub(crate) node_ids: Vec<String>,
+}
+
+/// All duplicates in the document rooted at `project`
+pub(crate) fn find(project: &Element) -> Vec<Duplicate> {
+    let mut out = Vec::new();
+    let mut targets = Vec::new();
+    collect(project, "target", &mut targets);
+
+    let mut across = Occurrences::default();
+    for target in targets.iter() {
+        let target_id = id(target);
+        let mut groups = Vec::new();
+        collect_groups(target, &mut groups);
+
+        let mut files: Vec<(String, &str, String)> = Vec::new(); // (name, group id, file id)
+        for group in groups.iter() {
+            let direct = named_children(group, "file");
+            out.extend(same_names(
+                &direct,
+                "file",
+                DuplicateScope::Group,
+                id(group),
+            ));
+            out.extend(same_names(
+                &named_children(group, "group"),
+                "group",
+                DuplicateScope::Group,
+                id(group),
+            ));
+            for (name, file) in direct {
+                files.push((name, id(group), id(file).to_string()));
+            }
+        }
+        out.extend(same_names(
+            &named_children(target, "group"),
+            "group",
+            DuplicateScope::Target,
+            target_id,
+        ));
+
+        // names spread over more than one group of this target
+        let mut by_name = Occurrences::default();
+        for (name, group_id, file_id) in files.iter() {
+            by_name.add(name, group_id, file_id);
+            across.add(name, target_id, file_id);
+        }
+        for (name, groups, node_ids) in by_name.entries {
+            if groups.len() > 1 {
+                out.push(Duplicate {
+                    kind: "file".into(),
+                    scope: DuplicateScope::Target,
+                    name,
+                    container_id: target_id.to_string(),
+                    node_ids,
+                });
+            }
+        }
+    }
+    for (name, targets, node_ids) in across.entries {
+        if targets.len() > 1 {
+            out.push(Duplicate {
+                kind: "file".into(),
+                scope: DuplicateScope::AcrossTargets,
+                name,
+                container_id: id(project).to_string(),
+                node_ids,
+            });
+        }
+    }
+    out
+}
+
+/// Ids of elements that exactly repeat an earlier sibling (same name and same
+/// content, ignoring node ids and indentation), paired with the id of the first one.
+/// Only duplicates within one group or among the groups of one parent qualify;
+/// the same file in different groups or targets is left alone.
+pub(crate) fn exact_duplicates(project: &Element, dups: &[Duplicate]) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    for dup in dups.iter() {
+        if dup.scope == DuplicateScope::AcrossTargets
+            || (dup.scope == DuplicateScope::Target && dup.kind == "file")
+        {
+            continue;
+        }
+        let elems: Vec<&Element> = dup
+            .node_ids
+            .iter()
+            .filter_map(|nid| crate::find_by_id(project, nid))
+            .collect();
+        let mut kept: Vec<&Element> = Vec::new();
+        for el in elems {
+            match kept.iter().find(|k| same_content(k, el)) {
+                Some(k) => out.push((id(el).to_string(), id(k).to_string())),
+                None => kept.push(el),
+            }
+        }
+    }
+    out
+}
+
+/// name -> (distinct containers, node ids) in order of first appearance
+#[derive(Default)]
+struct Occurrences<'a> {
+    index: HashMap<String, usize>,
+    entries: Vec<(String, Vec<&'a str>, Vec<String>)>,
+}
+
+impl<'a> Occurrences<'a> {
+    fn add(&mut self, name: &str, container: &'a str, node_id: &str) {
+        let i = *self.index.entry(name.to_string()).or_insert_with(|| {
+            self.entries
+                .push((name.to_string(), Vec::new(), Vec::new()));
+            self.entries.len() - 1
+        });
+        let (_, containers, ids) = &mut self.entries[i];
+        if !containers.contains(&container) {
+            containers.push(container);
+        }
+        ids.push(node_id.to_string());
+    }
+}
+
+fn id(el: &Element) -> &str {
+    node_id_of(el).unwrap_or_default()
+}
+
+/// all elements of `kind` at or below `el` in the tree
+fn collect<'a>(el: &'a Element, kind: &str, out: &mut Vec<&'a Element>) {
+    for (_rel, child) in tree_children(el) {
+        if child.name == kind {
+            out.push(child);
+        }
+        collect(child, kind, out);
+    }
+}
+
+/// groups belonging to `el` itself (nested groups included, nested targets not)
+fn collect_groups<'a>(el: &'a Element, out: &mut Vec<&'a Element>) {
+    for (_rel, child) in tree_children(el) {
+        if child.name == "group" {
+            out.push(child);
+            collect_groups(child, out);
+        }
+    }
+}
+
+/// (name, element) of the tree children of `el` with the given element name
+fn named_children<'a>(el: &'a Element, kind: &str) -> Vec<(String, &'a Element)> {
+    tree_children(el)
+        .into_iter()
+        .filter(|(_, c)| c.name == kind)
+        .filter_map(|(_, c)| Some((get_child_text(c, "name")?.trim().to_string(), c)))
+        .collect()
+}
+
+fn same_names(
+    items: &[(String, &Element)],
+    kind: &str,
+    scope: DuplicateScope,
+    container_id: &str,
+) -> Vec<Duplicate> {
+    let mut by_name = Occurrences::default();
+    for (name, el) in items.iter() {
+        by_name.add(name, container_id, id(el));
+    }
+    by_name
+        .entries
+        .into_iter()
+        .filter(|(_, _, ids)| ids.len() > 1)
+        .map(|(name, _, node_ids)| Duplicate {
+            kind: kind.to_string(),
+            scope,
+            name,
+            container_id: container_id.to_string(),
+            node_ids,
+        })
+        .collect()
+}
+
+/// Structural equality ignoring node ids, whitespace-only text and comments
+fn same_content(a: &Element, b: &Element) -> bool {
+    let attrs = |e: &Element| {
+        e.attributes
+            .iter()
+            .filter(|(k, _)| *k != NODE_ID_ATTR)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<HashMap<String, String>>()
+    };
+    fn content(e: &Element) -> Vec<&XMLNode> {
+        e.children
+            .iter()
+            .filter(|c| {
+                matches!(c, XMLNode::Element(_))
+                    || matches!(c, XMLNode::Text(t) if !t.trim().is_empty())
+            })
+            .collect()
+    }
+    if a.name != b.name || attrs(a) != attrs(b) {
+        return false;
+    }
+    let (ca, cb) = (content(a), content(b));
+    ca.len() == cb.len()
+        && ca.iter().zip(cb.iter()).all(|pair| match pair {
+            (XMLNode::Element(x), XMLNode::Element(y)) => same_content(x, y),
+            (XMLNode::Text(x), XMLNode::Text(y)) => x.trim() == y.trim(),
+            _ => false,
+        })
+}
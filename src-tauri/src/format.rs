@@ -0,0 +1,473 @@
+//! Round-trip formatting: keep BOM, XML declaration, line endings, indentation,
+//! comments, empty-element forms and the spelling of tags and text (quotes,
+//! character references) of the original file so that saving only touches
+//! edited nodes.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use xmltree::{Element, EmitterConfig, ParseError, ParserConfig, XMLNode};
+
+/// 元ファイルの体裁（保存時に再現する）
+#[derive(Clone, Debug)]
+pub(crate) struct DocFormat {
+    /// everything before the root element (BOM, `<?xml ...?>`, comments, doctype)
+    prolog: String,
+    /// everything after the root element's end tag
+    epilog: String,
+    /// line break for new lines: "\r\n" when most lines of the file end with it.
+    /// Existing line breaks are kept in the DOM's text nodes as they are.
+    newline: &'static str,
+    /// one indentation level (e.g. two spaces); None if the file is not indented
+    indent: Option<String>,
+    /// how each element that was empty in the file was written, by node id
+    empty_forms: HashMap<String, EmptyForm>,
+    /// source text of tags and text runs the emitter spells differently (single
+    /// quotes, `&quot;`, `&#10;`, ...), by the emitter's spelling
+    spellings: HashMap<String, String>,
+}
+
+/// How an element without content is written
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum EmptyForm {
+    /// `<x />` (also used for new elements)
+    Padded,
+    /// `<x/>`
+    Tight,
+    /// `<x></x>`
+    Open,
+}
+
+impl DocFormat {
+    pub(crate) fn detect(text: &str) -> Self {
+        let body_start = prolog_len(text);
+        let body_end = body_start + content_len(&text[body_start..]);
+        let crlf = text.matches("\r\n").count();
+        let lf = text.matches('\n').count() - crlf;
+        DocFormat {
+            prolog: text[..body_start].to_string(),
+            epilog: text[body_end..].to_string(),
+            newline: if crlf > lf { "\r\n" } else { "\n" },
+            indent: detect_indent(&text[body_start..body_end]),
+            empty_forms: HashMap::new(),
+            spellings: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn indent(&self) -> Option<&str> {
+        self.indent.as_deref()
+    }
+
+    pub(crate) fn newline(&self) -> &'static str {
+        self.newline
+    }
+
+    /// Remember how `text`, the file `root` (with node ids assigned) was parsed
+    /// from, writes its elements: the form of every empty element, and the
+    /// spelling of tags and text runs that write_body would change
+    pub(crate) fn record_source(&mut self, root: &Element, text: &str) {
+        let body_start = prolog_len(text);
+        let body = &text[body_start..body_start + content_len(&text[body_start..])];
+        let mut elements = Vec::new();
+        preorder(root, &mut elements);
+        self.empty_forms.clear();
+        for (el, (_, form)) in elements.into_iter().zip(start_tags(body)) {
+            if el.children.is_empty() {
+                if let Some(id) = crate::node_id_of(el) {
+                    self.empty_forms.insert(id.to_string(), form);
+                }
+            }
+        }
+
+        // the emitter's output has the same tags and text runs as the file, so
+        // they pair up one to one; a piece spelled the same way decodes the same
+        self.spellings.clear();
+        let Ok(written) = write_body(&crate::strip_node_ids(root), &self.empty_forms(root)) else {
+            return;
+        };
+        let (source, output) = (pieces(body), pieces(&written));
+        if source.len() != output.len() {
+            return;
+        }
+        for (s, w) in source.into_iter().zip(output) {
+            let (s, w) = (&body[s], &written[w]);
+            if s != w && s.starts_with('<') == w.starts_with('<') {
+                self.spellings
+                    .entry(w.to_string())
+                    .or_insert_with(|| s.to_string());
+            }
+        }
+    }
+
+    /// Put back the file's spelling of the tags and text runs of `xml` (output of
+    /// write_body) that were not edited
+    pub(crate) fn restore_spellings(&self, xml: &str) -> String {
+        if self.spellings.is_empty() {
+            return xml.to_string();
+        }
+        let mut out = String::with_capacity(xml.len());
+        for piece in pieces(xml) {
+            let written = &xml[piece];
+            out.push_str(self.spellings.get(written).map_or(written, |s| s.as_str()));
+        }
+        out
+    }
+
+    /// Form of each element below (and at) `el` in document order, for write_body
+    pub(crate) fn empty_forms(&self, el: &Element) -> Vec<EmptyForm> {
+        let mut elements = Vec::new();
+        preorder(el, &mut elements);
+        elements
+            .into_iter()
+            .map(|e| {
+                crate::node_id_of(e)
+                    .and_then(|id| self.empty_forms.get(id))
+                    .copied()
+                    .unwrap_or(EmptyForm::Padded)
+            })
+            .collect()
+    }
+
+    /// Put the serialized root element back between the original prolog and epilog
+    pub(crate) fn render(&self, body: &str) -> String {
+        format!("{}{}{}", self.prolog, body, self.epilog)
+    }
+}
+
+pub(crate) fn preorder<'a>(el: &'a Element, out: &mut Vec<&'a Element>) {
+    out.push(el);
+    for child in el.children.iter() {
+        if let XMLNode::Element(e) = child {
+            preorder(e, out);
+        }
+    }
+}
+
+/// Parse keeping whitespace and comments as nodes, so they survive serialization
+pub(crate) fn parse_preserving(text: &str) -> Result<Element, ParseError> {
+    let config = ParserConfig::new()
+        .trim_whitespace(false)
+        .whitespace_to_characters(true)
+        .ignore_comments(false);
+    Element::parse_with_config(text.as_bytes(), config)
+}
+
+/// Serialize only the root element (no declaration, no added indentation).
+/// Empty elements are written in the form `forms` gives for them (indexed by
+/// element in document order; `<name />` for missing entries).
+pub(crate) fn write_body(el: &Element, forms: &[EmptyForm]) -> Result<String, String> {
+    let config = EmitterConfig::new()
+        .write_document_declaration(false)
+        .perform_indent(false)
+        .normalize_empty_elements(true)
+        .pad_self_closing(true);
+    let mut vec = Vec::new();
+    el.write_with_config(&mut vec, config)
+        .map_err(|e| format!("serialize err: {}", e))?;
+    let xml = String::from_utf8(vec).map_err(|e| format!("utf8 err: {}", e))?;
+
+    // the emitter writes every empty element as `<name />`: switch to the wanted form
+    let mut out = String::with_capacity(xml.len());
+    let mut last = 0;
+    for (i, (range, written)) in start_tags(&xml).into_iter().enumerate() {
+        let wanted = forms.get(i).copied().unwrap_or(EmptyForm::Padded);
+        if written != EmptyForm::Padded || wanted == EmptyForm::Padded {
+            continue;
+        }
+        let head = &xml[range.start..range.end - " />".len()];
+        out.push_str(&xml[last..range.start]);
+        out.push_str(head);
+        match wanted {
+            EmptyForm::Tight => out.push_str("/>"),
+            _ => {
+                let name = head[1..].split_whitespace().next().unwrap_or_default();
+                out.push_str(&format!("></{}>", name));
+            }
+        }
+        last = range.end;
+    }
+    out.push_str(&xml[last..]);
+    Ok(out)
+}
+
+/// Start tags of `xml` in document order with their byte range and how they
+/// end: `<x />` Padded, `<x/>` Tight, `<x>` Open. Comments, CDATA sections,
+/// processing instructions, doctype and end tags are skipped.
+pub(crate) fn start_tags(xml: &str) -> Vec<(Range<usize>, EmptyForm)> {
+    markup(xml)
+        .into_iter()
+        .filter(|r| !matches!(xml.as_bytes().get(r.start + 1), Some(b'!' | b'?' | b'/')))
+        .map(|r| {
+            let tag = &xml[r.clone()];
+            let form = if !tag.ends_with("/>") {
+                EmptyForm::Open
+            } else if tag[..tag.len() - 2].ends_with(char::is_whitespace) {
+                EmptyForm::Padded
+            } else {
+                EmptyForm::Tight
+            };
+            (r, form)
+        })
+        .collect()
+}
+
+/// `xml` split into text runs and markup, alternating and starting with a
+/// (possibly empty) text run, so that the pieces of two spellings of one
+/// document pair up
+fn pieces(xml: &str) -> Vec<Range<usize>> {
+    let mut out = Vec::new();
+    let mut last = 0;
+    for r in markup(xml) {
+        out.push(last..r.start);
+        last = r.end;
+        out.push(r);
+    }
+    out.push(last..xml.len());
+    out
+}
+
+/// Byte ranges of all tags, comments, CDATA sections, processing instructions
+/// and doctype declarations of `xml` in document order
+fn markup(xml: &str) -> Vec<Range<usize>> {
+    let bytes = xml.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while let Some(offset) = xml[i..].find('<') {
+        let start = i + offset;
+        let rest = &xml[start..];
+        let skip_to = if rest.starts_with("<!--") {
+            Some("-->")
+        } else if rest.starts_with("<![CDATA[") {
+            Some("]]>")
+        } else if rest.starts_with("<?") {
+            Some("?>")
+        } else if rest.starts_with("<!") || rest.starts_with("</") {
+            Some(">")
+        } else {
+            None
+        };
+        if let Some(close) = skip_to {
+            i = start + rest.find(close).map_or(rest.len(), |e| e + close.len());
+            out.push(start..i);
+            continue;
+        }
+        // '>' may appear inside quoted attribute values
+        let mut quote = None;
+        let mut end = start + 1;
+        while end < bytes.len() {
+            match (quote, bytes[end]) {
+                (Some(q), c) if c == q => quote = None,
+                (None, c @ (b'"' | b'\'')) => quote = Some(c),
+                (None, b'>') => break,
+                _ => {}
+            }
+            end += 1;
+        }
+        end = (end + 1).min(bytes.len());
+        out.push(start..end);
+        i = end;
+    }
+    out
+}
+
+/// length of BOM, declaration, comments, doctype and whitespace before the root element
+fn prolog_len(text: &str) -> usize {
+    let mut pos = 0;
+    if text.starts_with('\u{feff}') {
+        pos += '\u{feff}'.len_utf8();
+    }
+    loop {
+        let rest = &text[pos..];
+        let trimmed = rest.trim_start();
+        pos += rest.len() - trimmed.len();
+        let close = if trimmed.starts_with("<?") {
+            "?>"
+        } else if trimmed.starts_with("<!--") {
+            "-->"
+        } else if trimmed.starts_with("<!") {
+            // doctype; an internal subset ends with "]>"
+            if trimmed[..trimmed.find('>').unwrap_or(trimmed.len())].contains('[') {
+                "]>"
+            } else {
+                ">"
+            }
+        } else {
+            return pos;
+        };
+        match trimmed.find(close) {
+            Some(i) => pos += i + close.len(),
+            None => return pos,
+        }
+    }
+}
+
+/// length of `text` up to and including the root element's end tag
+/// (trailing whitespace, comments and PIs belong to the epilog)
+fn content_len(text: &str) -> usize {
+    let mut end = text.len();
+    loop {
+        let trimmed = text[..end].trim_end();
+        end = trimmed.len();
+        let open = if trimmed.ends_with("-->") {
+            "<!--"
+        } else if trimmed.ends_with("?>") {
+            "<?"
+        } else {
+            return end;
+        };
+        match trimmed.rfind(open) {
+            Some(i) => end = i,
+            None => return end,
+        }
+    }
+}
+
+/// first indentation found at the start of a line, e.g. "  " or "\t"
+fn detect_indent(body: &str) -> Option<String> {
+    body.lines().find_map(|line| {
+        let ws: String = line
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect();
+        if !ws.is_empty() && line[ws.len()..].starts_with('<') {
+            Some(ws)
+        } else {
+            None
+        }
+    })
+}
+
+pub(crate) fn is_whitespace_text(node: &XMLNode) -> bool {
+    matches!(node, XMLNode::Text(t) if t.trim().is_empty())
+}
+
+/// Remove the child node at `pos` together with the whitespace in front of it,
+/// so no blank line is left behind
+pub(crate) fn remove_child_at(children: &mut Vec<XMLNode>, pos: usize) -> XMLNode {
+    let node = children.remove(pos);
+    if pos > 0 && is_whitespace_text(&children[pos - 1]) {
+        children.remove(pos - 1);
+    }
+    node
+}
+
+/// Indent newly inserted elements (those for which `is_new` is true) like the
+/// rest of the document. Existing nodes and their whitespace are left alone.
+/// `depth` is the depth of `el`'s children (the root's children are depth 1).
+pub(crate) fn format_new_elements(
+    el: &mut Element,
+    depth: usize,
+    unit: &str,
+    newline: &str,
+    is_new: &dyn Fn(&Element) -> bool,
+) {
+    let mut i = 0;
+    while i < el.children.len() {
+        let new = matches!(&el.children[i], XMLNode::Element(e) if is_new(e));
+        if !new {
+            if let XMLNode::Element(e) = &mut el.children[i] {
+                format_new_elements(e, depth + 1, unit, newline, is_new);
+            }
+            i += 1;
+            continue;
+        }
+        if let XMLNode::Element(e) = &mut el.children[i] {
+            reindent(e, depth + 1, unit, newline);
+        }
+        // whitespace before the new element
+        let line = format!("{}{}", newline, unit.repeat(depth));
+        if i > 0 && is_whitespace_text(&el.children[i - 1]) {
+            el.children[i - 1] = XMLNode::Text(line);
+        } else {
+            el.children.insert(i, XMLNode::Text(line));
+            i += 1;
+        }
+        // whitespace after it: before the next element, or the closing tag's indent
+        let next_is_element = el.children[i + 1..]
+            .iter()
+            .find(|c| !is_whitespace_text(c))
+            .is_some_and(|c| matches!(c, XMLNode::Element(_) | XMLNode::Comment(_)));
+        let after = if next_is_element {
+            format!("{}{}", newline, unit.repeat(depth))
+        } else {
+            format!("{}{}", newline, unit.repeat(depth.saturating_sub(1)))
+        };
+        match el.children.get(i + 1) {
+            Some(n) if is_whitespace_text(n) => {
+                if !next_is_element {
+                    el.children[i + 1] = XMLNode::Text(after);
+                }
+            }
+            _ => el.children.insert(i + 1, XMLNode::Text(after)),
+        }
+        i += 1;
+    }
+}
+
+/// Re-indent a whole (new or moved) subtree for the given depth of its children
+pub(crate) fn reindent(el: &mut Element, depth: usize, unit: &str, newline: &str) {
+    let has_elements = el
+        .children
+        .iter()
+        .any(|c| matches!(c, XMLNode::Element(_) | XMLNode::Comment(_)));
+    if !has_elements {
+        return;
+    }
+    el.children.retain(|c| !is_whitespace_text(c));
+    let mut out = Vec::with_capacity(el.children.len() * 2 + 1);
+    for mut child in el.children.drain(..) {
+        if let XMLNode::Element(e) = &mut child {
+            reindent(e, depth + 1, unit, newline);
+        }
+        out.push(XMLNode::Text(format!("{}{}", newline, unit.repeat(depth))));
+        out.push(child);
+    }
+    out.push(XMLNode::Text(format!(
+        "{}{}",
+        newline,
+        unit.repeat(depth.saturating_sub(1))
+    )));
+    el.children = out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_keeps_empty_forms_and_line_endings() {
+        let text = "\u{feff}<?xml version=\"1.0\"?>\r\n<project>\r\n  <targets></targets>\n  \
+                    <target>\r\n    <groups/>\r\n    <files />\n  </target>\r\n</project>\r\n";
+        let mut dom = parse_preserving(text).unwrap();
+        crate::assign_node_ids(&mut dom);
+        let mut format = DocFormat::detect(text);
+        format.record_source(&dom, text);
+        let body = write_body(&crate::strip_node_ids(&dom), &format.empty_forms(&dom)).unwrap();
+        let body = format.restore_spellings(&body);
+        assert_eq!(format.render(&body), text);
+        assert_eq!(format.newline(), "\r\n");
+    }
+
+    #[test]
+    fn round_trip_keeps_quotes_and_references() {
+        let text =
+            "<project a='1' b=\"x &amp; &quot;y&quot;\">\n  <name>a &quot;b&quot; &#65;</name>\n  \
+            <file n='q'/>\n  <file n='r'>t &gt; u</file>\n</project>\n";
+        let mut dom = parse_preserving(text).unwrap();
+        crate::assign_node_ids(&mut dom);
+        let mut format = DocFormat::detect(text);
+        format.record_source(&dom, text);
+        let write = |dom: &Element| {
+            let body = write_body(&crate::strip_node_ids(dom), &format.empty_forms(dom)).unwrap();
+            format.render(&format.restore_spellings(&body))
+        };
+        assert_eq!(write(&dom), text);
+
+        // an edited element is written the emitter's way, the other lines stay as they were
+        let Some(XMLNode::Element(file)) = dom.children.get_mut(3) else {
+            panic!("no <file>");
+        };
+        file.attributes.insert("n".into(), "s".into());
+        let expected = text.replace("<file n='q'/>", "<file n=\"s\"/>");
+        assert_eq!(write(&dom), expected);
+    }
+}
@@ -10,28 +10,525 @@ use uuid::Uuid;
 
 use xmltree::Element;
 
+mod disk;
+mod duplicates;
+mod format;
+mod node_path;
+mod schema;
+mod sort;
+use disk::FileStatus;
+use format::DocFormat;
+use node_path::{NodePath, Step};
+use sort::SortOptions;
+
 use sxd_document::parser as sxd_parser;
 use sxd_xpath::{Context, Factory, Value};
 
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
 use std::{collections::HashSet, path::Path, process::Command};
-use tauri::Manager;
+use tauri::{Emitter, Manager};
 
 /// CachedDoc: 各ファイルごとのキャッシュ
 struct CachedDoc {
     path: PathBuf,
-    dom: Element, // editable DOM (xmltree)
+    dom: Element, // editable DOM (xmltree), every element tagged with NODE_ID_ATTR
     text: String, // latest serialized text
-    // node map: node_id -> pseudo-xpath (we use indexes)
-    node_map: HashMap<String, String>,
+    // node map: node_id -> positional path, rebuilt by reindex()
+    node_map: HashMap<String, NodePath>,
+    history: EditHistory,
+    saved_dom: Element,  // DOM as last loaded from / saved to disk
+    change_count: usize, // number of edits (including undo/redo) since load
+    disk: DiskState,     // on-disk file as last read/written by us
+    format: DocFormat,   // BOM / declaration / newline / indent of the original file
+    // ids of elements moved in from elsewhere; re-indented for their new depth on reindex()
+    reformat: HashSet<String>,
+}
+
+/// ディスク上のファイル状態（外部変更の検出用）
+#[derive(Clone, PartialEq)]
+struct DiskState {
+    hash: u64,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl DiskState {
+    fn from_bytes(bytes: &[u8], meta: Option<fs::Metadata>) -> Self {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        DiskState {
+            hash: hasher.finish(),
+            len: bytes.len() as u64,
+            modified: meta.and_then(|m| m.modified().ok()),
+        }
+    }
+
+    /// Read the current state of `path`; None when the file no longer exists
+    fn read(path: &Path) -> Result<Option<Self>, std::io::Error> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(Some(Self::from_bytes(&bytes, fs::metadata(path).ok()))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Why load_files could not load a file. Serialized as `{ "kind": "...", ... }`;
+/// parse errors carry the 1-based line/column and the text of that line.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum LoadError {
+    Io {
+        path: String,
+        message: String,
+    },
+    /// the file is not valid UTF-8
+    Encoding {
+        path: String,
+        message: String,
+    },
+    /// not well-formed XML
+    Syntax {
+        path: String,
+        message: String,
+        line: u64,
+        column: u64,
+        snippet: String,
+    },
+    /// the file ends inside an element, comment, ...
+    UnexpectedEof {
+        path: String,
+        message: String,
+        line: u64,
+        column: u64,
+        snippet: String,
+    },
+    /// well-formed, but not supported by the parser
+    Unsupported {
+        path: String,
+        message: String,
+    },
+}
+
+impl LoadError {
+    fn read(path: &str, e: std::io::Error) -> Self {
+        let (path, message) = (path.to_string(), e.to_string());
+        if e.kind() == std::io::ErrorKind::InvalidData {
+            LoadError::Encoding { path, message }
+        } else {
+            LoadError::Io { path, message }
+        }
+    }
+
+    fn parse(path: &str, text: &str, e: xmltree::ParseError) -> Self {
+        use xml::common::Position;
+        use xml::reader::ErrorKind;
+        let path = path.to_string();
+        let e = match e {
+            xmltree::ParseError::MalformedXml(e) => e,
+            other => {
+                return LoadError::Unsupported {
+                    path,
+                    message: other.to_string(),
+                }
+            }
+        };
+        let pos = e.position();
+        let (line, column) = (pos.row + 1, pos.column + 1);
+        let snippet = text
+            .lines()
+            .nth(pos.row as usize)
+            .unwrap_or_default()
+            .trim_start_matches('\u{feff}')
+            .to_string();
+        let message = e.msg().to_string();
+        // xml-rs reports an unclosed root element as a syntax error; only its message tells
+        let eof = match e.kind() {
+            ErrorKind::UnexpectedEof => true,
+            ErrorKind::Syntax(m) => m.starts_with("Unexpected end of stream"),
+            _ => false,
+        };
+        match e.kind() {
+            _ if eof => LoadError::UnexpectedEof {
+                path,
+                message,
+                line,
+                column,
+                snippet,
+            },
+            ErrorKind::Io(_) => LoadError::Io { path, message },
+            _ => LoadError::Syntax {
+                path,
+                message,
+                line,
+                column,
+                snippet,
+            },
+        }
+    }
+}
+
+/// Errors returned by save_file. Serialized as `{ "kind": "...", ... }` so the
+/// UI can offer "overwrite" / "reload" on a conflict.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum SaveError {
+    NotLoaded {
+        path: String,
+    },
+    /// the file was changed or removed by another program since load_files/save_file
+    Conflict {
+        path: String,
+        missing: bool,
+        disk_modified: Option<u64>, // unix millis
+    },
+    Io {
+        path: String,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::NotLoaded { path } => write!(f, "not loaded: {}", path),
+            SaveError::Conflict { path, missing, .. } if *missing => {
+                write!(f, "conflict: {} was removed on disk", path)
+            }
+            SaveError::Conflict { path, .. } => {
+                write!(f, "conflict: {} was modified on disk", path)
+            }
+            SaveError::Io { path, message } => write!(f, "write err: {}: {}", path, message),
+        }
+    }
+}
+
+/// 編集履歴（undo/redo）。各エントリは編集前のDOMスナップショットとラベルを持つ
+#[derive(Default)]
+struct EditHistory {
+    undo: Vec<HistoryEntry>,
+    redo: Vec<HistoryEntry>,
+}
+
+impl EditHistory {
+    /// the entry the next undo (or redo) applies
+    fn latest(&self, undo: bool) -> Option<&HistoryEntry> {
+        if undo {
+            self.undo.last()
+        } else {
+            self.redo.last()
+        }
+    }
+}
+
+struct HistoryEntry {
+    label: String,
+    dom: Element, // DOM (including node ids) before/after the labelled edit
+    link: Option<EditLink>,
+}
+
+/// Ties together the entries of one edit that changed several documents (a move
+/// between documents), so that they are undone and redone as one step
+#[derive(Clone)]
+struct EditLink {
+    id: String,
+    /// every document changed by the edit
+    paths: Vec<String>,
+}
+
+/// Upper bound of undo entries kept per document
+const HISTORY_LIMIT: usize = 100;
+
+#[derive(Serialize)]
+struct HistoryState {
+    path: String,
+    undo_label: Option<String>,
+    redo_label: Option<String>,
+}
+
+/// 永続ノードIDを保持する属性名（DOM上のみ。シリアライズ時に除去する）
+const NODE_ID_ATTR: &str = "__node_id";
+
+impl CachedDoc {
+    fn new(path: PathBuf, mut dom: Element, text: String, disk: DiskState) -> Self {
+        assign_node_ids(&mut dom);
+        let mut format = DocFormat::detect(&text);
+        format.record_source(&dom, &text);
+        let mut doc = CachedDoc {
+            disk,
+            format,
+            path,
+            saved_dom: dom.clone(),
+            dom,
+            text,
+            node_map: HashMap::new(),
+            history: EditHistory::default(),
+            reformat: HashSet::new(),
+            change_count: 0,
+        };
+        doc.reindex();
+        doc
+    }
+
+    /// Finish one command's edits on this document: record `before` as a single
+    /// undo entry (if anything changed), refresh node paths and the serialized text.
+    fn finish_edit(&mut self, before: Element, label: String) -> Result<(), String> {
+        self.finish_linked_edit(before, label, None)
+    }
+
+    /// finish_edit for an edit that also changed other documents (see EditLink)
+    fn finish_linked_edit(
+        &mut self,
+        before: Element,
+        label: String,
+        link: Option<EditLink>,
+    ) -> Result<(), String> {
+        if self.dom == before {
+            return Ok(());
+        }
+        self.history.undo.push(HistoryEntry {
+            label,
+            dom: before,
+            link,
+        });
+        if self.history.undo.len() > HISTORY_LIMIT {
+            self.history.undo.remove(0);
+        }
+        self.history.redo.clear();
+        self.change_count += 1;
+        self.reindex();
+        self.text = self.serialize()?;
+        Ok(())
+    }
+
+    /// True when the DOM differs from what is on disk (undoing back to the
+    /// saved state makes the document clean again)
+    fn is_dirty(&self) -> bool {
+        self.dom != self.saved_dom
+    }
+
+    fn history_state(&self, path: &str) -> HistoryState {
+        HistoryState {
+            path: path.to_string(),
+            undo_label: self.history.undo.last().map(|h| h.label.clone()),
+            redo_label: self.history.redo.last().map(|h| h.label.clone()),
+        }
+    }
+
+    /// Serialize the DOM in the style of the original file (see format.rs)
+    fn serialize(&self) -> Result<String, String> {
+        let body = serialize_element(&self.dom, &self.format)?;
+        Ok(self.format.render(&body))
+    }
+
+    /// Tag newly inserted elements and recompute the pseudo-xpath of every tree node.
+    /// Must be called after each DOM mutation so node_map never points at a shifted index.
+    fn reindex(&mut self) {
+        // new elements have no id yet: indent them (and moved ones) like their neighbours first
+        if let Some(unit) = self.format.indent().map(|u| u.to_string()) {
+            let reformat = std::mem::take(&mut self.reformat);
+            let is_new = |e: &Element| match node_id_of(e) {
+                Some(id) => reformat.contains(id),
+                None => true,
+            };
+            let newline = self.format.newline();
+            format::format_new_elements(&mut self.dom, 1, &unit, newline, &is_new);
+        }
+        self.reformat.clear();
+        assign_node_ids(&mut self.dom);
+        self.node_map.clear();
+        fn walk(el: &Element, path: &NodePath, node_map: &mut HashMap<String, NodePath>) {
+            if let Some(id) = node_id_of(el) {
+                node_map.insert(id.to_string(), path.clone());
+            }
+            for (rel, child) in tree_children(el) {
+                walk(child, &path.join(&rel), node_map);
+            }
+        }
+        let root_path = NodePath::root(&self.dom.name);
+        walk(&self.dom, &root_path, &mut self.node_map);
+    }
+
+    /// Path of a node id in this document, if the id belongs here
+    fn path_of(&self, node_id: &str) -> Option<&NodePath> {
+        self.node_map.get(node_id)
+    }
+
+    /// Schema violations with the line/column of the offending element in `text`
+    fn diagnostics(&self, schema: &schema::Schema) -> Vec<schema::Diagnostic> {
+        let mut diagnostics = schema.validate(&self.dom);
+        schema::locate(&mut diagnostics, &self.dom, &self.text);
+        diagnostics.sort_by_key(|d| (d.line, d.column));
+        diagnostics
+    }
+}
+
+/// Tree children of a project/target/group element in document order, with their
+/// path relative to `el` (e.g. `target[1]`, `groups/group[2]`, `files/file[1]`).
+/// Targets nest inside targets and groups inside groups to any depth; children may
+/// sit directly under the parent or inside the plural wrapper (<targets>, <groups>, <files>).
+fn tree_children(el: &Element) -> Vec<(Vec<Step>, &Element)> {
+    let kinds: &[&str] = match el.name.as_str() {
+        "project" => &["target"],
+        "target" => &["target", "group"],
+        "group" => &["group", "file"],
+        _ => &[],
+    };
+    let mut out = Vec::new();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for child in el.children.iter() {
+        let xmltree::XMLNode::Element(c) = child else {
+            continue;
+        };
+        if kinds.contains(&c.name.as_str()) {
+            let n = counts.entry(&c.name).or_insert(0);
+            *n += 1;
+            out.push((vec![Step::new(&c.name, *n)], c));
+        } else if let Some(kind) = kinds.iter().find(|k| c.name == format!("{}s", k)) {
+            let w = counts.entry(&c.name).or_insert(0);
+            *w += 1;
+            let wrapper = if *w == 1 {
+                Step::first(&c.name)
+            } else {
+                Step::new(&c.name, *w)
+            };
+            for (i, item) in children_named(c, kind).enumerate() {
+                out.push((vec![wrapper.clone(), Step::new(kind, i + 1)], item));
+            }
+        }
+    }
+    out
+}
+
+/// Give every element without an id a fresh one. Existing ids are kept, so ids
+/// follow their element through sorting, insertion and deletion of siblings.
+fn assign_node_ids(el: &mut Element) {
+    if !el.attributes.contains_key(NODE_ID_ATTR) {
+        el.attributes
+            .insert(NODE_ID_ATTR.to_string(), Uuid::new_v4().to_string());
+    }
+    for child in el.children.iter_mut() {
+        if let xmltree::XMLNode::Element(e) = child {
+            assign_node_ids(e);
+        }
+    }
+}
+
+fn node_id_of(el: &Element) -> Option<&str> {
+    el.attributes.get(NODE_ID_ATTR).map(|s| s.as_str())
+}
+
+/// Element with the given node id anywhere below (or at) `el`
+fn find_by_id<'a>(el: &'a Element, id: &str) -> Option<&'a Element> {
+    if node_id_of(el) == Some(id) {
+        return Some(el);
+    }
+    el.children.iter().find_map(|c| match c {
+        xmltree::XMLNode::Element(e) => find_by_id(e, id),
+        _ => None,
+    })
+}
+
+fn find_by_id_mut<'a>(el: &'a mut Element, id: &str) -> Option<&'a mut Element> {
+    if node_id_of(el) == Some(id) {
+        return Some(el);
+    }
+    el.children.iter_mut().find_map(|c| match c {
+        xmltree::XMLNode::Element(e) => find_by_id_mut(e, id),
+        _ => None,
+    })
+}
+
+/// Parent of the element with the given node id, and the element's index in its children
+fn find_parent_by_id_mut<'a>(el: &'a mut Element, id: &str) -> Option<(&'a mut Element, usize)> {
+    let pos = el
+        .children
+        .iter()
+        .position(|c| matches!(c, xmltree::XMLNode::Element(e) if node_id_of(e) == Some(id)));
+    if let Some(pos) = pos {
+        return Some((el, pos));
+    }
+    el.children.iter_mut().find_map(|c| match c {
+        xmltree::XMLNode::Element(e) => find_parent_by_id_mut(e, id),
+        _ => None,
+    })
+}
+
+/// Positional path of the element with the given node id (any element, not only tree nodes)
+fn element_path(root: &Element, id: &str) -> Option<NodePath> {
+    fn walk(el: &Element, path: NodePath, id: &str) -> Option<NodePath> {
+        if node_id_of(el) == Some(id) {
+            return Some(path);
+        }
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for c in el.children.iter() {
+            if let xmltree::XMLNode::Element(e) = c {
+                let n = counts.entry(&e.name).or_insert(0);
+                *n += 1;
+                if let Some(p) = walk(e, path.child(Step::new(&e.name, *n)), id) {
+                    return Some(p);
+                }
+            }
+        }
+        None
+    }
+    walk(root, NodePath::root(&root.name), id)
+}
+
+/// Copy of the element tree with all node ids removed (for writing to disk)
+fn strip_node_ids(el: &Element) -> Element {
+    let mut out = el.clone();
+    fn strip(el: &mut Element) {
+        el.attributes.shift_remove(NODE_ID_ATTR);
+        for child in el.children.iter_mut() {
+            if let xmltree::XMLNode::Element(e) = child {
+                strip(e);
+            }
+        }
+    }
+    strip(&mut out);
+    out
+}
+
+/// Check that every node id is known to some loaded document.
+/// Ids of deleted elements (or from a previous load) are reported instead of
+/// being resolved to whatever element now sits at the old position.
+fn ensure_known_ids(cache: &HashMap<String, CachedDoc>, node_ids: &[String]) -> Result<(), String> {
+    for nid in node_ids {
+        if !cache.values().any(|entry| entry.node_map.contains_key(nid)) {
+            return Err(format!("stale or unknown node id: {}", nid));
+        }
+    }
+    Ok(())
 }
 
 /// グローバルキャッシュ（簡易）
 static CACHE: Lazy<Mutex<HashMap<String, CachedDoc>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
+/// close要求時に未保存の変更を無視して終了するかどうか（close_window(force=true)で設定）
+static FORCE_CLOSE: AtomicBool = AtomicBool::new(false);
+
 #[derive(Serialize)]
 struct FileSummary {
     path: String,
     project_name: Option<String>,
+    // problems found by the built-in schema (see validate_schema)
+    diagnostics: Vec<schema::Diagnostic>,
+    // None when the file was loaded
+    error: Option<LoadError>,
+}
+
+#[derive(Serialize, Clone)]
+struct OpenDocument {
+    path: String,
+    project_name: Option<String>,
+    dirty: bool,
+    change_count: usize,
 }
 
 #[derive(Serialize)]
@@ -40,6 +537,9 @@ struct TreeNode {
     label: String,
     children: Vec<TreeNode>,
     node_type: String,
+    // file nodes only (None for project/target/group)
+    exists: Option<bool>,
+    status: Option<FileStatus>,
 }
 
 #[tauri::command]
@@ -47,33 +547,45 @@ async fn load_files(paths: Vec<String>) -> Result<Vec<FileSummary>, String> {
     let mut results = Vec::new();
     let mut cache = CACHE.lock().await;
 
+    // a file that cannot be loaded is reported in its summary; the others still load
     for p in paths {
-        let pathbuf = PathBuf::from(&p);
-        let text = fs::read_to_string(&pathbuf).map_err(|e| format!("read error: {}", e))?;
-        // parse via xmltree
-        let root =
-            Element::parse(text.as_bytes()).map_err(|e| format!("xml parse error: {}", e))?;
-        // create cache entry
-        let doc = CachedDoc {
-            path: pathbuf.clone(),
-            dom: root,
-            text: text.clone(),
-            node_map: HashMap::new(),
+        let summary = match load_file(&p) {
+            Ok((doc, text)) => {
+                let diagnostics = doc.diagnostics(&schema::Schema::builtin());
+                cache.insert(p.clone(), doc);
+                FileSummary {
+                    path: p.clone(),
+                    // try to extract project/name for quick summary
+                    project_name: extract_project_name_from_text(&text).ok(),
+                    diagnostics,
+                    error: None,
+                }
+            }
+            Err(e) => FileSummary {
+                path: p.clone(),
+                project_name: None,
+                diagnostics: Vec::new(),
+                error: Some(e),
+            },
         };
-        cache.insert(p.clone(), doc);
-
-        // try to extract project/name for quick summary
-        let project_name = extract_project_name_from_text(&text).ok();
-
-        results.push(FileSummary {
-            path: p.clone(),
-            project_name,
-        });
+        results.push(summary);
     }
 
     Ok(results)
 }
 
+/// Read and parse one project file into a new cache entry (and its text)
+fn load_file(p: &str) -> Result<(CachedDoc, String), LoadError> {
+    let pathbuf = PathBuf::from(p);
+    let text = fs::read_to_string(&pathbuf).map_err(|e| LoadError::read(p, e))?;
+    let disk = DiskState::from_bytes(text.as_bytes(), fs::metadata(&pathbuf).ok());
+    // parse via xmltree
+    let root = format::parse_preserving(&text).map_err(|e| LoadError::parse(p, &text, e))?;
+    // create cache entry (assigns node ids)
+    let doc = CachedDoc::new(pathbuf, root, text.clone(), disk);
+    Ok((doc, text))
+}
+
 /// Helper: try to quickly extract /project/name from raw text using sxd xpath
 fn extract_project_name_from_text(text: &str) -> Result<String, String> {
     let package = sxd_parser::parse(text).map_err(|e| format!("sxd parse err {:?}", e))?;
@@ -98,15 +610,152 @@ fn extract_project_name_from_text(text: &str) -> Result<String, String> {
     }
 }
 
-/// Build tree JSON for client (following the 4-level specification)
+/// Result of query_xpath: matched nodes, or the scalar value of the expression
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum QueryResult {
+    Nodes { nodes: Vec<QueryNode> },
+    String { value: String },
+    Number { value: f64 },
+    Boolean { value: bool },
+}
+
+#[derive(Serialize)]
+struct QueryNode {
+    key: String,       // tree key (node id) of the matched node or its nearest tree ancestor
+    node_id: String, // node id of the matched element (or of the element owning an attribute/text)
+    node_type: String, // "project" / "target" / "group" / "file" of `key`
+    path: NodePath,  // path of `key`
+    value: String,   // XPath string-value of the matched node
+}
+
+/// Evaluate an XPath 1.0 expression against a loaded (possibly edited) document.
+/// e.g. `/project/target[name='tname1']//file[substring(name, string-length(name) - 1) = '.c']`
 #[tauri::command]
-async fn get_tree(path: String) -> Result<Vec<TreeNode>, String> {
-    let mut cache = CACHE.lock().await;
-    let entry = cache.get_mut(&path).ok_or("not loaded")?;
+async fn query_xpath(path: String, expr: String) -> Result<QueryResult, String> {
+    let cache = CACHE.lock().await;
+    let entry = cache.get(&path).ok_or("not loaded")?;
+    evaluate_xpath(entry, &expr, |value, ids| match value {
+        Value::Nodeset(ns) => {
+            let nodes = ns
+                .document_order()
+                .into_iter()
+                .filter_map(|node| query_node(entry, ids, node))
+                .collect();
+            QueryResult::Nodes { nodes }
+        }
+        Value::String(value) => QueryResult::String { value },
+        Value::Number(value) => QueryResult::Number { value },
+        Value::Boolean(value) => QueryResult::Boolean { value },
+    })
+}
+
+/// Node ids of the elements of the document given to sxd_xpath. The document is
+/// serialized without node ids (expressions must not see the internal attribute),
+/// so elements are paired with the DOM's by their position in document order.
+struct XPathIds<'d>(HashMap<sxd_document::dom::Element<'d>, String>);
+
+impl<'d> XPathIds<'d> {
+    // sxd elements hash and compare by node identity, which their interior
+    // mutability does not change
+    #[allow(clippy::mutable_key_type)]
+    fn new(doc: &sxd_document::dom::Document<'d>, dom: &Element) -> Self {
+        fn walk<'d>(
+            el: sxd_document::dom::Element<'d>,
+            out: &mut Vec<sxd_document::dom::Element<'d>>,
+        ) {
+            out.push(el);
+            for child in el.children() {
+                if let sxd_document::dom::ChildOfElement::Element(c) = child {
+                    walk(c, out);
+                }
+            }
+        }
+        let mut elements = Vec::new();
+        for child in doc.root().children() {
+            if let sxd_document::dom::ChildOfRoot::Element(e) = child {
+                walk(e, &mut elements);
+            }
+        }
+        let mut dom_elements = Vec::new();
+        format::preorder(dom, &mut dom_elements);
+        let ids = elements
+            .into_iter()
+            .zip(dom_elements)
+            .filter_map(|(e, d)| Some((e, node_id_of(d)?.to_string())))
+            .collect();
+        XPathIds(ids)
+    }
+
+    fn get(&self, el: sxd_document::dom::Element<'d>) -> Option<&str> {
+        self.0.get(&el).map(|s| s.as_str())
+    }
+}
+
+/// Evaluate `expr` with sxd_xpath on the document (without node ids); `f` gets
+/// the value and the node ids of the evaluated document's elements
+fn evaluate_xpath<T>(
+    entry: &CachedDoc,
+    expr: &str,
+    f: impl for<'d> FnOnce(Value<'d>, &XPathIds<'d>) -> T,
+) -> Result<T, String> {
+    let text = format::write_body(&strip_node_ids(&entry.dom), &[])?;
+    let package = sxd_parser::parse(&text).map_err(|e| format!("sxd parse err {:?}", e))?;
+    let doc = package.as_document();
+    let ids = XPathIds::new(&doc, &entry.dom);
+    let factory = Factory::new();
+    let xpath = factory
+        .build(expr)
+        .map_err(|e| format!("xpath build {:?}", e))?
+        .ok_or("compile failed")?;
+    let context = Context::new();
+    let value = xpath
+        .evaluate(&context, doc.root())
+        .map_err(|e| format!("evaluate {:?}", e))?;
+    Ok(f(value, &ids))
+}
+
+/// Map a matched sxd node to the element it belongs to and its nearest tree node
+fn query_node(
+    entry: &CachedDoc,
+    ids: &XPathIds,
+    node: sxd_xpath::nodeset::Node,
+) -> Option<QueryNode> {
+    use sxd_xpath::nodeset::Node;
+    let id_of = |n: &Node| match n {
+        Node::Element(e) => ids.get(*e).map(|s| s.to_string()),
+        _ => None,
+    };
+    let mut cur = Some(node);
+    let mut node_id = None;
+    while let Some(n) = cur {
+        if let Some(id) = id_of(&n) {
+            if node_id.is_none() {
+                node_id = Some(id.clone());
+            }
+            if let Some(tree_path) = entry.path_of(&id) {
+                return Some(QueryNode {
+                    key: id,
+                    node_id: node_id?,
+                    node_type: tree_path.kind().to_string(),
+                    path: tree_path.clone(),
+                    value: node.string_value(),
+                });
+            }
+        }
+        cur = n.parent();
+    }
+    None
+}
 
-    // clear node_map (we will repopulate with fresh ids)
-    entry.node_map.clear();
-    let root = &entry.dom;
+/// Build tree JSON for client: project -> targets (nested) -> groups (nested) -> files
+/// Node keys are the persistent node ids, so they stay valid across edits.
+#[tauri::command]
+async fn get_tree(path: String) -> Result<Vec<TreeNode>, String> {
+    // the file check reads directories, so it runs on a snapshot without the lock
+    let (dom, base) = snapshot(&path).await?;
+    let issues = check_files_blocking(dom.clone(), base).await?;
+    let root = &dom;
 
     // Expect root element "project"
     // 1st level: file node -> label "/project/name"
@@ -115,192 +764,1661 @@ async fn get_tree(path: String) -> Result<Vec<TreeNode>, String> {
         "/project/{}",
         get_child_text(root, "name").unwrap_or_else(|| "<no-name>".into())
     );
-    let root_id = Uuid::new_v4().to_string();
-    entry
-        .node_map
-        .insert(root_id.clone(), "/project".to_string());
-
-    // children: targets
-    let mut targets_nodes = Vec::new();
-    for (t_idx, target_el) in get_children_by_name(root, "target").into_iter().enumerate() {
-        // pseudo xpath: /project/target[t_idx+1]
-        let t_xpath = format!("/project/target[{}]", t_idx + 1);
-        let t_id = Uuid::new_v4().to_string();
-        let t_label = format!("{}/name", t_xpath);
-        // store mapping
-        entry.node_map.insert(t_id.clone(), t_xpath.clone());
-
-        // groups under target
-        let mut groups_nodes = Vec::new();
-        if let Some(groups_el) = find_child_element(&target_el, "groups") {
-            for (g_idx, group_el) in get_children_by_name(groups_el, "group")
-                .into_iter()
-                .enumerate()
-            {
-                let g_xpath = format!("{} /groups/group[{}]", t_xpath, g_idx + 1).replace(" ", "");
-                let g_id = Uuid::new_v4().to_string();
-                let g_name =
-                    get_child_text(&group_el, "name").unwrap_or_else(|| "<no-name>".into());
-                entry.node_map.insert(g_id.clone(), g_xpath.clone());
-
-                // files under group
-                let mut file_nodes = Vec::new();
-                if let Some(files_el) = find_child_element(&group_el, "files") {
-                    for (f_idx, file_el) in get_children_by_name(files_el, "file")
-                        .into_iter()
-                        .enumerate()
-                    {
-                        let f_xpath = format!("{}/files/file[{}]", g_xpath, f_idx + 1);
-                        let f_id = Uuid::new_v4().to_string();
-                        let f_name =
-                            get_child_text(&file_el, "name").unwrap_or_else(|| "<no-name>".into());
-                        entry.node_map.insert(f_id.clone(), f_xpath.clone());
-
-                        file_nodes.push(TreeNode {
-                            key: f_id,
-                            label: f_name,
-                            children: vec![],
-                            node_type: "file".into(),
-                        });
-                    }
-                }
-
-                groups_nodes.push(TreeNode {
-                    key: g_id,
-                    label: g_name,
-                    children: file_nodes,
-                    node_type: "group".into(),
-                });
-            }
-        }
 
-        targets_nodes.push(TreeNode {
-            key: t_id,
-            label: get_child_text(&target_el, "name").unwrap_or_else(|| "<no-name>".into()),
-            children: groups_nodes,
-            node_type: "target".into(),
+    let statuses: HashMap<String, (FileStatus, bool)> =
+        issues.into_iter().fold(HashMap::new(), |mut m, issue| {
+            m.entry(issue.node_id)
+                .or_insert((issue.status, issue.exists));
+            m
         });
-    }
 
     let tree = vec![TreeNode {
-        key: root_id,
+        key: tree_key(root)?,
         label: root_label,
-        children: targets_nodes,
+        children: build_tree_nodes(root, &statuses)?,
         node_type: "project".into(),
+        exists: None,
+        status: None,
     }];
 
     Ok(tree)
 }
 
-/// Sort files inside each group node specified by node_ids (which refer to group nodes)
+/// Recursively build TreeNodes for targets, groups and files below `el`.
+/// `statuses` holds the problems found by check_files (with whether the name as
+/// written opens the file); other files are Ok.
+fn build_tree_nodes(
+    el: &Element,
+    statuses: &HashMap<String, (FileStatus, bool)>,
+) -> Result<Vec<TreeNode>, String> {
+    let mut nodes = Vec::new();
+    for (_rel, child) in tree_children(el) {
+        let key = tree_key(child)?;
+        let state = (child.name == "file").then(|| {
+            statuses
+                .get(&key)
+                .copied()
+                .unwrap_or((FileStatus::Ok, true))
+        });
+        nodes.push(TreeNode {
+            label: get_child_text(child, "name").unwrap_or_else(|| "<no-name>".into()),
+            children: build_tree_nodes(child, statuses)?,
+            node_type: child.name.clone(),
+            exists: state.map(|(_, exists)| exists),
+            status: state.map(|(status, _)| status),
+            key,
+        });
+    }
+    Ok(nodes)
+}
+
+fn tree_key(el: &Element) -> Result<String, String> {
+    node_id_of(el)
+        .map(|s| s.to_string())
+        .ok_or_else(|| format!("element <{}> has no node id", el.name))
+}
+
+#[derive(Serialize, Default, Debug)]
+struct SortSummary {
+    /// groups whose files were sorted
+    groups_sorted: usize,
+    /// groups that ended up at a different position (only with `group_names`)
+    groups_reordered: usize,
+    /// files that ended up at a different position
+    files_reordered: usize,
+}
+
+/// Sort files inside the nodes specified by node_ids. A group id sorts its own
+/// files and those of its nested groups; a project or target id sorts every group
+/// beneath it. With `options.group_names` the groups themselves are ordered by name too.
 #[tauri::command]
-async fn sort_groups(node_ids: Vec<String>, ascending: bool) -> Result<(), String> {
+async fn sort_groups(node_ids: Vec<String>, options: SortOptions) -> Result<SortSummary, String> {
     let mut cache = CACHE.lock().await;
+    ensure_known_ids(&cache, &node_ids)?;
+    let mut summary = SortSummary::default();
     // find which cached doc contains each node id
-    for (path, entry) in cache.iter_mut() {
-        for nid in node_ids.iter() {
-            if let Some(xpath) = entry.node_map.get(nid) {
-                // only handle group nodes (xpath containing /groups/group[..])
-                if !xpath.contains("/groups/group[") {
-                    continue;
-                }
-                // locate group element in entry.dom using our pseudo-xpath
-                if let Some((parent_files_el, mut file_elems)) =
-                    find_group_files_mut(&mut entry.dom, xpath)
-                {
-                    // sort file_elems by <name> text
-                    file_elems.sort_by(|a, b| {
-                        let na = get_child_text(a, "name").unwrap_or_default();
-                        let nb = get_child_text(b, "name").unwrap_or_default();
-                        if ascending {
-                            na.cmp(&nb)
-                        } else {
-                            nb.cmp(&na)
-                        }
-                    });
-                    // rebuild parent's <files> children from sorted file_elems
-                    parent_files_el
-                        .children
-                        .retain(|c| !is_element_named(c, "file"));
-                    for fe in file_elems {
-                        parent_files_el
-                            .children
-                            .push(xmltree::XMLNode::Element(fe.clone()));
-                    }
-                }
+    for (_path, entry) in cache.iter_mut() {
+        let paths: Vec<NodePath> = node_ids
+            .iter()
+            .filter_map(|nid| entry.path_of(nid).cloned())
+            .filter(|p| p.kind() != "file")
+            .collect();
+        let before = entry.dom.clone();
+        let mut sorted = 0;
+        for node_path in paths.iter() {
+            // a node below another selected node is already covered by it
+            if paths.iter().any(|p| node_path.is_descendant_of(p)) {
+                continue;
             }
+            let Some(el) = node_path.resolve_mut(&mut entry.dom) else {
+                continue;
+            };
+            let mut s = SortSummary::default();
+            sort_subtree(el, &options, &mut s);
+            sorted += s.groups_sorted;
+            summary.groups_sorted += s.groups_sorted;
+            summary.groups_reordered += s.groups_reordered;
+            summary.files_reordered += s.files_reordered;
         }
-        // after edits, update entry.text to serialized xml
-        entry.text = serialize_element(&entry.dom)?;
+        // after edits, record history, refresh node paths and update entry.text
+        entry.finish_edit(before, format!("Sort {} groups", sorted))?;
     }
-    Ok(())
+    Ok(summary)
 }
 
-/// Add a new <file><name>file_name</name></file> to each group node in node_ids
-#[tauri::command]
-async fn add_file_to_groups(node_ids: Vec<String>, file_name: String) -> Result<(), String> {
-    let mut cache = CACHE.lock().await;
-    for (_path, entry) in cache.iter_mut() {
-        for nid in node_ids.iter() {
-            if let Some(xpath) = entry.node_map.get(nid) {
-                if !xpath.contains("/groups/group[") {
-                    continue;
-                }
-                if let Some((_files_el, _file_elems)) = find_group_files_mut(&mut entry.dom, xpath)
-                {
-                    // find files element mutable and push a new file element
-                    if let Some((files_el, _)) = find_group_files_mut(&mut entry.dom, xpath) {
-                        let mut new_file = Element::new("file");
-                        let mut name_el = Element::new("name");
-                        name_el
-                            .children
-                            .push(xmltree::XMLNode::Text(file_name.clone()));
-                        new_file.children.push(xmltree::XMLNode::Element(name_el));
-                        files_el.children.push(xmltree::XMLNode::Element(new_file));
-                    }
+/// Sort the files of every group at or below `el` (project, target, group or a
+/// <targets>/<groups> wrapper), and the groups by name when requested
+fn sort_subtree(el: &mut Element, options: &SortOptions, summary: &mut SortSummary) {
+    if el.name == "group" {
+        summary.groups_sorted += 1;
+        summary.files_reordered += sort_child_slots(el, "file", |a, b| options.compare_files(a, b));
+        for child in el.children.iter_mut() {
+            if let xmltree::XMLNode::Element(files) = child {
+                if files.name == "files" {
+                    summary.files_reordered +=
+                        sort_child_slots(files, "file", |a, b| options.compare_files(a, b));
                 }
             }
         }
-        entry.text = serialize_element(&entry.dom)?;
     }
-    Ok(())
+    if options.group_names && matches!(el.name.as_str(), "target" | "group" | "groups") {
+        summary.groups_reordered += sort_child_slots(el, "group", |a, b| options.compare(a, b));
+    }
+    for child in el.children.iter_mut() {
+        if let xmltree::XMLNode::Element(e) = child {
+            if matches!(e.name.as_str(), "target" | "group" | "targets" | "groups") {
+                sort_subtree(e, options, summary);
+            }
+        }
+    }
+}
+
+/// Stable-sort the `name` children of `el` by their <name> text and put them back
+/// into the slots of the old children, leaving whitespace and comments between
+/// them where they were. Returns how many of them changed position.
+fn sort_child_slots(
+    el: &mut Element,
+    name: &str,
+    cmp: impl Fn(&str, &str) -> std::cmp::Ordering,
+) -> usize {
+    let mut elems: Vec<Element> = children_named(el, name).cloned().collect();
+    let old_ids: Vec<Option<String>> = elems
+        .iter()
+        .map(|e| node_id_of(e).map(|s| s.to_string()))
+        .collect();
+    elems.sort_by(|a, b| {
+        let na = get_child_text(a, "name").unwrap_or_default();
+        let nb = get_child_text(b, "name").unwrap_or_default();
+        cmp(&na, &nb)
+    });
+    let moved = elems
+        .iter()
+        .zip(old_ids.iter())
+        .filter(|(e, id)| node_id_of(e) != id.as_deref())
+        .count();
+    let mut sorted_iter = elems.into_iter();
+    for child in el.children.iter_mut() {
+        if is_element_named(child, name) {
+            if let Some(e) = sorted_iter.next() {
+                *child = xmltree::XMLNode::Element(e);
+            }
+        }
+    }
+    moved
+}
+
+#[derive(Serialize)]
+struct DuplicateReport {
+    path: String,
+    duplicates: Vec<duplicates::Duplicate>,
+}
+
+/// Report duplicate file names within a group, within a target and across targets,
+/// and duplicate group names among sibling groups, in `path` or in every loaded document
+#[tauri::command]
+async fn find_duplicates(path: Option<String>) -> Result<Vec<DuplicateReport>, String> {
+    let cache = CACHE.lock().await;
+    let mut paths: Vec<String> = match &path {
+        Some(p) if !cache.contains_key(p) => return Err("not loaded".into()),
+        Some(p) => vec![p.clone()],
+        None => cache.keys().cloned().collect(),
+    };
+    paths.sort();
+    let mut reports = Vec::new();
+    for p in paths {
+        let entry = cache.get(&p).ok_or("not loaded")?;
+        reports.push(DuplicateReport {
+            path: p,
+            duplicates: duplicates::find(&entry.dom),
+        });
+    }
+    Ok(reports)
+}
+
+#[derive(Serialize)]
+struct DedupeReport {
+    path: String,
+    removed: Vec<RemovedDuplicate>,
+}
+
+#[derive(Serialize)]
+struct RemovedDuplicate {
+    node_id: String,
+    element_path: NodePath,
+    /// node id of the first occurrence, which is kept
+    kept_id: String,
+}
+
+/// Remove files and groups that exactly repeat an earlier one in the same group
+/// (or among the groups of the same parent), keeping the first occurrence.
+/// Same-named entries whose content differs, and the same file in different groups
+/// or targets, are only reported by find_duplicates. With `preview` nothing is changed.
+#[tauri::command]
+async fn dedupe(path: Option<String>, preview: bool) -> Result<Vec<DedupeReport>, String> {
+    let mut cache = CACHE.lock().await;
+    let mut paths: Vec<String> = match &path {
+        Some(p) if !cache.contains_key(p) => return Err("not loaded".into()),
+        Some(p) => vec![p.clone()],
+        None => cache.keys().cloned().collect(),
+    };
+    paths.sort();
+    let mut reports = Vec::new();
+    for p in paths {
+        let entry = cache.get_mut(&p).ok_or("not loaded")?;
+        let dups = duplicates::find(&entry.dom);
+        let mut removed = Vec::new();
+        let mut dom = entry.dom.clone();
+        for (node_id, kept_id) in duplicates::exact_duplicates(&entry.dom, &dups) {
+            // already gone with a removed duplicate group
+            if find_by_id(&dom, &node_id).is_none() {
+                continue;
+            }
+            let element_path = element_path(&entry.dom, &node_id).ok_or("node not found")?;
+            let (parent, pos) =
+                find_parent_by_id_mut(&mut dom, &node_id).ok_or("node not found")?;
+            format::remove_child_at(&mut parent.children, pos);
+            removed.push(RemovedDuplicate {
+                node_id,
+                element_path,
+                kept_id,
+            });
+        }
+        if !preview && !removed.is_empty() {
+            let before = std::mem::replace(&mut entry.dom, dom);
+            entry.finish_edit(before, format!("Remove {} duplicates", removed.len()))?;
+        }
+        reports.push(DedupeReport { path: p, removed });
+    }
+    Ok(reports)
+}
+
+#[derive(Serialize, Debug)]
+struct FileIssue {
+    node_id: String,
+    name: String,
+    status: FileStatus,
+    /// the name resolved against the project XML's directory
+    resolved: String,
+    /// on-disk spelling (case_mismatch / duplicate_on_disk)
+    actual: Option<String>,
+    /// node id of the earlier entry referring to the same file (duplicate_on_disk)
+    duplicate_of: Option<String>,
+    /// the name as written opens the file; false when missing, and for a case
+    /// mismatch on a case-sensitive file system
+    exists: bool,
+}
+
+/// Check every <file><name> of the document `path` against the file system,
+/// resolving names relative to the project file. Reports missing files, names
+/// whose case differs from the file on disk, and entries of one group that
+/// refer to the same file; files without problems are not listed.
+#[tauri::command]
+async fn validate_files(path: String) -> Result<Vec<FileIssue>, String> {
+    let (dom, base) = snapshot(&path).await?;
+    check_files_blocking(dom, base).await
+}
+
+/// DOM of a loaded document and the directory its file names are relative to,
+/// taken so that the file system can be read without holding the cache lock
+async fn snapshot(path: &str) -> Result<(Element, PathBuf), String> {
+    let cache = CACHE.lock().await;
+    let entry = cache.get(path).ok_or("not loaded")?;
+    let base = entry.path.parent().unwrap_or(Path::new("")).to_path_buf();
+    Ok((entry.dom.clone(), base))
+}
+
+async fn check_files_blocking(dom: Element, base: PathBuf) -> Result<Vec<FileIssue>, String> {
+    tauri::async_runtime::spawn_blocking(move || check_files(&dom, &base))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+fn check_files(dom: &Element, base: &Path) -> Vec<FileIssue> {
+    fn walk(el: &Element, resolver: &mut disk::Resolver, out: &mut Vec<FileIssue>) {
+        // on-disk path -> node id of the first entry in this group
+        let mut seen: HashMap<PathBuf, String> = HashMap::new();
+        for (_rel, child) in tree_children(el) {
+            if child.name != "file" {
+                walk(child, resolver, out);
+                continue;
+            }
+            let (Some(node_id), Some(name)) = (node_id_of(child), get_child_text(child, "name"))
+            else {
+                continue;
+            };
+            let r = resolver.resolve(&name);
+            // a case-insensitive file system opens the name as written as well
+            let exists = r.actual.is_some() && (!r.case_mismatch || r.path.exists());
+            let issue = |status, duplicate_of| FileIssue {
+                node_id: node_id.to_string(),
+                name: name.clone(),
+                status,
+                resolved: r.path.to_string_lossy().into_owned(),
+                actual: r.actual.as_ref().map(|a| a.to_string_lossy().into_owned()),
+                duplicate_of,
+                exists,
+            };
+            let Some(actual) = &r.actual else {
+                out.push(issue(FileStatus::Missing, None));
+                continue;
+            };
+            if r.case_mismatch {
+                out.push(issue(FileStatus::CaseMismatch, None));
+            }
+            match seen.get(actual) {
+                Some(first) => out.push(issue(FileStatus::DuplicateOnDisk, Some(first.clone()))),
+                None => {
+                    seen.insert(actual.clone(), node_id.to_string());
+                }
+            }
+        }
+    }
+    let mut resolver = disk::Resolver::new(base);
+    let mut out = Vec::new();
+    walk(dom, &mut resolver, &mut out);
+    out
+}
+
+/// Validate the document `path` against the project schema: the built-in rules,
+/// or those in `schema` (an .xsd file, or a JSON rules file in the form of
+/// schema::Schema). Diagnostics carry node ids and line/column in the current text.
+#[tauri::command]
+async fn validate_schema(
+    path: String,
+    schema: Option<String>,
+) -> Result<Vec<schema::Diagnostic>, String> {
+    let rules = match schema {
+        Some(s) => schema::Schema::load(Path::new(&s))?,
+        None => schema::Schema::builtin(),
+    };
+    let cache = CACHE.lock().await;
+    let entry = cache.get(&path).ok_or("not loaded")?;
+    Ok(entry.diagnostics(&rules))
+}
+
+/// Add a new <file><name>file_name</name></file> to each group node in node_ids
+#[tauri::command]
+async fn add_file_to_groups(node_ids: Vec<String>, file_name: String) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    ensure_known_ids(&cache, &node_ids)?;
+    for (_path, entry) in cache.iter_mut() {
+        let before = entry.dom.clone();
+        let mut added = 0;
+        for nid in node_ids.iter() {
+            if let Some(node_path) = entry.path_of(nid).cloned() {
+                if node_path.kind() != "group" {
+                    continue;
+                }
+                added += 1;
+                // find files element mutable and push a new file element
+                if let Some(files_el) = group_files_mut(&mut entry.dom, &node_path) {
+                    let new_file = new_named_element("file", &file_name);
+                    files_el.children.push(xmltree::XMLNode::Element(new_file));
+                }
+            }
+        }
+        entry.finish_edit(before, format!("Add {} to {} groups", file_name, added))?;
+    }
+    Ok(())
+}
+
+/// Warnings from add_files_from_disk, serialized as `{ "kind": "...", ... }`
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum AddFileWarning {
+    /// not below the project XML's directory; stored as "../..." (or absolute on another drive)
+    OutsideProject { file: String, name: String },
+    /// the group already lists this file; it is not added again
+    AlreadyListed {
+        file: String,
+        name: String,
+        group_id: String,
+    },
+    /// the chosen file does not exist; skipped
+    NotFound { file: String },
+}
+
+#[derive(Serialize)]
+struct AddFilesReport {
+    path: String,
+    added: Vec<AddedFile>,
+    warnings: Vec<AddFileWarning>,
+}
+
+#[derive(Serialize)]
+struct AddedFile {
+    group_id: String,
+    name: String,
+}
+
+/// Add files chosen from disk to each group node in node_ids. Folders in `paths`
+/// add every file below them that matches one of `patterns` (globs such as "*.c"
+/// or "src/**/*.h"). Names are stored relative to the project XML's directory,
+/// with the separator the project already uses.
+#[tauri::command]
+async fn add_files_from_disk(
+    node_ids: Vec<String>,
+    paths: Vec<String>,
+    patterns: Option<Vec<String>>,
+) -> Result<Vec<AddFilesReport>, String> {
+    let files = disk::collect_files(&paths, &patterns.unwrap_or_default())?;
+    let mut cache = CACHE.lock().await;
+    ensure_known_ids(&cache, &node_ids)?;
+    // check every target before any document is changed
+    for entry in cache.values() {
+        if let Some(nid) = node_ids
+            .iter()
+            .find(|nid| entry.path_of(nid).is_some_and(|p| p.kind() != "group"))
+        {
+            return Err(format!("not a group node: {}", nid));
+        }
+    }
+    // build every document's new DOM first and commit only once all succeeded
+    let mut results = Vec::new();
+    for (path, entry) in cache.iter() {
+        let groups: Vec<(String, NodePath)> = node_ids
+            .iter()
+            .filter_map(|nid| Some((nid.clone(), entry.path_of(nid)?.clone())))
+            .collect();
+        if groups.is_empty() {
+            continue;
+        }
+        let base = entry.path.parent().unwrap_or(Path::new(""));
+        let existing = file_names(&entry.dom);
+        let sep = disk::detect_separator(existing.iter().map(|s| s.as_str()));
+
+        let mut warnings = Vec::new();
+        let mut names = Vec::new();
+        for file in files.iter() {
+            let display = file.to_string_lossy().into_owned();
+            if !file.is_file() {
+                warnings.push(AddFileWarning::NotFound { file: display });
+                continue;
+            }
+            let (name, outside) = disk::name_for(base, file, sep);
+            if outside {
+                warnings.push(AddFileWarning::OutsideProject {
+                    file: display.clone(),
+                    name: name.clone(),
+                });
+            }
+            names.push((display, name));
+        }
+
+        let mut dom = entry.dom.clone();
+        let mut added = Vec::new();
+        let new_names: Vec<String> = names.iter().map(|(_, name)| name.clone()).collect();
+        for (group_id, group_path) in groups.iter() {
+            let appended = append_group_files(&mut dom, group_path, &new_names)?;
+            for ((file, name), appended) in names.iter().zip(appended) {
+                if appended {
+                    added.push(AddedFile {
+                        group_id: group_id.clone(),
+                        name: name.clone(),
+                    });
+                } else {
+                    warnings.push(AddFileWarning::AlreadyListed {
+                        file: file.clone(),
+                        name: name.clone(),
+                        group_id: group_id.clone(),
+                    });
+                }
+            }
+        }
+        results.push((path.clone(), dom, added, warnings));
+    }
+
+    let mut reports = Vec::new();
+    for (path, dom, added, warnings) in results {
+        let entry = cache.get_mut(&path).ok_or("not loaded")?;
+        let before = std::mem::replace(&mut entry.dom, dom);
+        entry.finish_edit(before, format!("Add {} files", added.len()))?;
+        reports.push(AddFilesReport {
+            path,
+            added,
+            warnings,
+        });
+    }
+    Ok(reports)
+}
+
+/// Append a <file> for each of `names` to the group's <files>, skipping names the
+/// group already lists (separator style and a leading "./" are ignored).
+/// Returns for each name whether it was appended.
+fn append_group_files(
+    dom: &mut Element,
+    group_path: &NodePath,
+    names: &[String],
+) -> Result<Vec<bool>, String> {
+    let group_el = group_path.resolve(dom).ok_or("node not found")?;
+    let mut listed: HashSet<String> = tree_children(group_el)
+        .into_iter()
+        .filter(|(_, c)| c.name == "file")
+        .filter_map(|(_, c)| get_child_text(c, "name"))
+        .map(|n| disk::name_key(&n))
+        .collect();
+    let files_el = group_files_mut(dom, group_path).ok_or("node not found")?;
+    let mut appended = Vec::with_capacity(names.len());
+    for name in names {
+        let new = listed.insert(disk::name_key(name));
+        if new {
+            files_el
+                .children
+                .push(xmltree::XMLNode::Element(new_named_element("file", name)));
+        }
+        appended.push(new);
+    }
+    Ok(appended)
+}
+
+/// <name> of every file in the document
+fn file_names(el: &Element) -> Vec<String> {
+    let mut out = Vec::new();
+    for (_rel, child) in tree_children(el) {
+        if child.name == "file" {
+            out.extend(get_child_text(child, "name"));
+        } else {
+            out.extend(file_names(child));
+        }
+    }
+    out
+}
+
+/// Delete file nodes specified by node_ids (these should be file-level node ids)
+#[tauri::command]
+async fn delete_file_nodes(node_ids: Vec<String>) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    ensure_known_ids(&cache, &node_ids)?;
+    let mut results = Vec::new();
+    for (path, entry) in cache.iter() {
+        let mut dom = entry.dom.clone();
+        let deleted = remove_file_nodes(entry, &mut dom, &node_ids);
+        if deleted > 0 {
+            results.push((path.clone(), dom, deleted));
+        }
+    }
+    for (path, dom, deleted) in results {
+        let entry = cache.get_mut(&path).ok_or("not loaded")?;
+        let before = std::mem::replace(&mut entry.dom, dom);
+        entry.finish_edit(before, format!("Delete {} files", deleted))?;
+    }
+    Ok(())
+}
+
+/// Remove the file nodes among `node_ids` that belong to `entry` from `dom`, a copy
+/// of its DOM; returns how many were removed. The caller swaps the copy in and
+/// records the edit with finish_edit, so a failing command leaves `entry` as it was.
+/// Elements are found by node id, so removing one never invalidates the next.
+fn remove_file_nodes(entry: &CachedDoc, dom: &mut Element, node_ids: &[String]) -> usize {
+    let files: Vec<&String> = node_ids
+        .iter()
+        .filter(|nid| entry.path_of(nid).is_some_and(|p| p.kind() == "file"))
+        .collect();
+    let mut deleted = 0;
+    for nid in files {
+        if let Some((parent, pos)) = find_parent_by_id_mut(dom, nid) {
+            format::remove_child_at(&mut parent.children, pos);
+            deleted += 1;
+        }
+    }
+    deleted
+}
+
+/// Bind a group to a folder (relative to the project file) and glob patterns,
+/// stored in the group as `<sync><folder>..</folder><pattern>..</pattern></sync>`.
+/// `folder` may be absolute; it is stored relative to the project like file names.
+/// None removes the binding.
+#[tauri::command]
+async fn bind_group_folder(
+    node_id: String,
+    folder: Option<String>,
+    patterns: Vec<String>,
+) -> Result<(), String> {
+    disk::compile_globs(&patterns)?;
+    let mut cache = CACHE.lock().await;
+    ensure_known_ids(&cache, std::slice::from_ref(&node_id))?;
+    let entry = cache
+        .values_mut()
+        .find(|e| e.path_of(&node_id).is_some())
+        .ok_or("node not found")?;
+    let group_path = entry.path_of(&node_id).cloned().ok_or("node not found")?;
+    if group_path.kind() != "group" {
+        return Err(format!("not a group node: {}", node_id));
+    }
+    let sep = disk::detect_separator(file_names(&entry.dom).iter().map(|s| s.as_str()));
+    let base = entry.path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let before = entry.dom.clone();
+    let group_el = group_path
+        .resolve_mut(&mut entry.dom)
+        .ok_or("node not found")?;
+    let label = match folder {
+        Some(folder) => {
+            let folder = if Path::new(&folder).is_absolute() {
+                disk::name_for(&base, Path::new(&folder), sep).0
+            } else {
+                folder
+            };
+            let mut sync = Element::new("sync");
+            sync.children
+                .push(xmltree::XMLNode::Element(text_element("folder", &folder)));
+            for p in patterns.iter() {
+                sync.children
+                    .push(xmltree::XMLNode::Element(text_element("pattern", p)));
+            }
+            match node_path::child_position(group_el, "sync", 1) {
+                Some(pos) => group_el.children[pos] = xmltree::XMLNode::Element(sync),
+                None => {
+                    // right after <name>, before <files> and nested groups
+                    let pos = node_path::child_position(group_el, "name", 1)
+                        .map(|p| p + 1)
+                        .unwrap_or(0);
+                    group_el
+                        .children
+                        .insert(pos, xmltree::XMLNode::Element(sync));
+                }
+            }
+            format!("Bind group to {}", folder)
+        }
+        None => {
+            if let Some(pos) = node_path::child_position(group_el, "sync", 1) {
+                format::remove_child_at(&mut group_el.children, pos);
+            }
+            "Unbind group folder".to_string()
+        }
+    };
+    entry.finish_edit(before, label)
+}
+
+/// Folder and patterns a group is bound to (see bind_group_folder)
+fn group_binding(group_el: &Element) -> Option<(String, Vec<String>)> {
+    let sync = find_child_element(group_el, "sync")?;
+    let folder = get_child_text(sync, "folder")?;
+    let patterns = children_named(sync, "pattern")
+        .filter_map(|p| p.get_text().map(|t| t.trim().to_string()))
+        .collect();
+    Some((folder.trim().to_string(), patterns))
+}
+
+#[derive(Serialize)]
+struct SyncDiff {
+    path: String,
+    /// the bound folder resolved against the project directory
+    folder: String,
+    /// names of files on disk that the group does not list yet
+    added: Vec<String>,
+    /// listed files inside the folder that no longer exist
+    removed: Vec<SyncRemoved>,
+}
+
+#[derive(Serialize)]
+struct SyncRemoved {
+    node_id: String,
+    name: String,
+}
+
+/// Compare a bound group (see bind_group_folder) with its folder on disk. Returns
+/// the files to add and the listed files that no longer exist; unless `preview`,
+/// the diff is applied as one undoable edit. Listed files outside the folder or
+/// not matching the patterns are left alone.
+#[tauri::command]
+async fn sync_group(node_id: String, preview: bool) -> Result<SyncDiff, String> {
+    // the folder is walked on a copy of the group, without holding the cache lock
+    let (path, group_el, base, sep) = {
+        let cache = CACHE.lock().await;
+        ensure_known_ids(&cache, std::slice::from_ref(&node_id))?;
+        let (path, entry) = cache
+            .iter()
+            .find(|(_, e)| e.path_of(&node_id).is_some())
+            .ok_or("node not found")?;
+        let group_path = entry.path_of(&node_id).ok_or("node not found")?;
+        if group_path.kind() != "group" {
+            return Err(format!("not a group node: {}", node_id));
+        }
+        let group_el = group_path.resolve(&entry.dom).ok_or("node not found")?;
+        let base = entry.path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let sep = disk::detect_separator(file_names(&entry.dom).iter().map(|s| s.as_str()));
+        (path.clone(), group_el.clone(), base, sep)
+    };
+    let binding = group_binding(&group_el)
+        .ok_or_else(|| format!("group is not bound to a folder: {}", node_id))?;
+    let (folder, patterns) = binding.clone();
+    let diff = tauri::async_runtime::spawn_blocking(move || {
+        group_sync_diff(path, &group_el, &base, sep, &folder, &patterns)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    if preview {
+        return Ok(diff);
+    }
+
+    let mut cache = CACHE.lock().await;
+    let entry = cache.get_mut(&diff.path).ok_or("not loaded")?;
+    // the group may have been removed or rebound while the folder was read
+    let group_path = entry
+        .path_of(&node_id)
+        .filter(|p| p.kind() == "group")
+        .cloned()
+        .ok_or_else(|| format!("group was removed while syncing: {}", node_id))?;
+    let group_el = group_path.resolve(&entry.dom).ok_or("node not found")?;
+    if group_binding(group_el).as_ref() != Some(&binding) {
+        return Err(format!("group was rebound while syncing: {}", node_id));
+    }
+    let mut dom = entry.dom.clone();
+    if !diff.added.is_empty() {
+        append_group_files(&mut dom, &group_path, &diff.added)?;
+    }
+    let ids: Vec<String> = diff.removed.iter().map(|r| r.node_id.clone()).collect();
+    remove_file_nodes(entry, &mut dom, &ids);
+    let before = std::mem::replace(&mut entry.dom, dom);
+    let label = format!(
+        "Sync group: +{} -{} files",
+        diff.added.len(),
+        diff.removed.len()
+    );
+    entry.finish_edit(before, label)?;
+    Ok(diff)
+}
+
+/// Files of the bound `folder` (matching `patterns`) that `group_el` does not
+/// list, and listed files inside it that no longer exist. Reads the disk: call it
+/// off the async runtime.
+fn group_sync_diff(
+    path: String,
+    group_el: &Element,
+    base: &Path,
+    sep: char,
+    folder: &str,
+    patterns: &[String],
+) -> Result<SyncDiff, String> {
+    let mut resolver = disk::Resolver::new(base);
+    // walk the folder as spelled on disk so paths compare with resolved file names
+    let folder_path = resolver
+        .resolve(folder)
+        .actual
+        .filter(|p| p.is_dir())
+        .ok_or_else(|| format!("folder not found: {}", folder))?;
+    let globs = disk::compile_globs(patterns)?;
+
+    let mut listed = HashSet::new();
+    let mut removed = Vec::new();
+    for (_rel, file) in tree_children(group_el) {
+        if file.name != "file" {
+            continue;
+        }
+        let (Some(id), Some(name)) = (node_id_of(file), get_child_text(file, "name")) else {
+            continue;
+        };
+        let r = resolver.resolve(&name);
+        match r.actual {
+            Some(actual) => {
+                listed.insert(actual);
+            }
+            None => {
+                let inside = r
+                    .path
+                    .strip_prefix(&folder_path)
+                    .is_ok_and(|rel| disk::matches_any(&globs, rel));
+                if inside {
+                    removed.push(SyncRemoved {
+                        node_id: id.to_string(),
+                        name,
+                    });
+                }
+            }
+        }
+    }
+    let added: Vec<String> =
+        disk::collect_files(&[folder_path.to_string_lossy().into_owned()], patterns)?
+            .into_iter()
+            .filter(|f| !listed.contains(&disk::normalize(f)))
+            .map(|f| disk::name_for(base, &f, sep).0)
+            .collect();
+
+    Ok(SyncDiff {
+        path,
+        folder: folder_path.to_string_lossy().into_owned(),
+        added,
+        removed,
+    })
+}
+
+/// Add a new <group><name>group_name</name></group> under each target (or group) in node_ids.
+/// The <groups> wrapper is created if the parent has none yet.
+#[tauri::command]
+async fn add_group(node_ids: Vec<String>, group_name: String) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    ensure_known_ids(&cache, &node_ids)?;
+    for (_path, entry) in cache.iter_mut() {
+        let before = entry.dom.clone();
+        let mut added = 0;
+        for nid in node_ids.iter() {
+            if let Some(node_path) = entry.path_of(nid).cloned() {
+                if !matches!(node_path.kind(), "target" | "group") {
+                    continue;
+                }
+                if let Some(parent_el) = node_path.resolve_mut(&mut entry.dom) {
+                    let groups_el = ensure_child_mut(parent_el, "groups");
+                    let new_group = new_named_element("group", &group_name);
+                    groups_el
+                        .children
+                        .push(xmltree::XMLNode::Element(new_group));
+                    added += 1;
+                }
+            }
+        }
+        entry.finish_edit(
+            before,
+            format!("Add group {} to {} targets/groups", group_name, added),
+        )?;
+    }
+    Ok(())
+}
+
+/// Add a new <target><name>target_name</name></target> under each project (or target)
+/// in node_ids, right after the parent's last target. Without any target yet it goes
+/// into the <targets> wrapper, which is created if missing.
+#[tauri::command]
+async fn add_target(node_ids: Vec<String>, target_name: String) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    ensure_known_ids(&cache, &node_ids)?;
+    for (_path, entry) in cache.iter_mut() {
+        let before = entry.dom.clone();
+        let mut added = 0;
+        for nid in node_ids.iter() {
+            if let Some(node_path) = entry.path_of(nid).cloned() {
+                if !matches!(node_path.kind(), "project" | "target") {
+                    continue;
+                }
+                if let Some(parent_el) = node_path.resolve_mut(&mut entry.dom) {
+                    let new_target =
+                        xmltree::XMLNode::Element(new_named_element("target", &target_name));
+                    let last = parent_el
+                        .children
+                        .iter()
+                        .rposition(|c| is_element_named(c, "target"));
+                    match last {
+                        Some(pos) => parent_el.children.insert(pos + 1, new_target),
+                        None => ensure_child_mut(parent_el, "targets")
+                            .children
+                            .push(new_target),
+                    }
+                    added += 1;
+                }
+            }
+        }
+        entry.finish_edit(
+            before,
+            format!("Add target {} to {} projects/targets", target_name, added),
+        )?;
+    }
+    Ok(())
+}
+
+/// Rename a project, target or group by setting the text of its <name> child
+#[tauri::command]
+async fn rename_node(node_id: String, new_name: String) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    ensure_known_ids(&cache, std::slice::from_ref(&node_id))?;
+    for (_path, entry) in cache.iter_mut() {
+        let Some(node_path) = entry.path_of(&node_id).cloned() else {
+            continue;
+        };
+        if !matches!(node_path.kind(), "project" | "target" | "group") {
+            return Err(format!("cannot rename a {} node", node_path.kind()));
+        }
+        let before = entry.dom.clone();
+        let el = node_path
+            .resolve_mut(&mut entry.dom)
+            .ok_or_else(|| format!("node not found: {}", node_path))?;
+        if find_child_element(el, "name").is_none() {
+            el.children
+                .insert(0, xmltree::XMLNode::Element(Element::new("name")));
+        }
+        let name_el = ensure_child_mut(el, "name");
+        name_el.children = vec![xmltree::XMLNode::Text(new_name.clone())];
+        entry.finish_edit(
+            before,
+            format!("Rename {} to {}", node_path.kind(), new_name),
+        )?;
+    }
+    Ok(())
+}
+
+/// Delete target and group nodes (with everything below them)
+#[tauri::command]
+async fn delete_nodes(node_ids: Vec<String>) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    ensure_known_ids(&cache, &node_ids)?;
+    for (_path, entry) in cache.iter_mut() {
+        let nodes: Vec<&String> = node_ids
+            .iter()
+            .filter(|nid| {
+                entry
+                    .path_of(nid)
+                    .is_some_and(|p| matches!(p.kind(), "target" | "group"))
+            })
+            .collect();
+        let before = entry.dom.clone();
+        let mut deleted = 0;
+        // found by id, so earlier removals never shift the later ones
+        for nid in nodes {
+            // an id may already be gone when one of its ancestors was deleted before it
+            if let Some((parent, pos)) = find_parent_by_id_mut(&mut entry.dom, nid) {
+                format::remove_child_at(&mut parent.children, pos);
+                deleted += 1;
+            }
+        }
+        entry.finish_edit(before, format!("Delete {} targets/groups", deleted))?;
+    }
+    Ok(())
+}
+
+/// Duplicate each group in node_ids (with its files and nested groups) right after
+/// the original. The copy gets fresh node ids and is named `new_name` or "<name> copy".
+#[tauri::command]
+async fn duplicate_groups(node_ids: Vec<String>, new_name: Option<String>) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    ensure_known_ids(&cache, &node_ids)?;
+    for (_path, entry) in cache.iter_mut() {
+        let before = entry.dom.clone();
+        let mut duplicated = 0;
+        for nid in node_ids.iter() {
+            let Some(node_path) = entry.path_of(nid) else {
+                continue;
+            };
+            if node_path.kind() != "group" {
+                continue;
+            }
+            let Some((parent, pos)) = find_parent_by_id_mut(&mut entry.dom, nid) else {
+                continue;
+            };
+            let xmltree::XMLNode::Element(original) = &parent.children[pos] else {
+                continue;
+            };
+            let mut copy = strip_node_ids(original);
+            let name = match &new_name {
+                Some(n) => n.clone(),
+                None => format!(
+                    "{} copy",
+                    get_child_text(original, "name").unwrap_or_default()
+                ),
+            };
+            ensure_child_mut(&mut copy, "name").children = vec![xmltree::XMLNode::Text(name)];
+            parent
+                .children
+                .insert(pos + 1, xmltree::XMLNode::Element(copy));
+            duplicated += 1;
+        }
+        entry.finish_edit(before, format!("Duplicate {} groups", duplicated))?;
+    }
+    Ok(())
+}
+
+/// Move file or group nodes into the destination group (or groups into a target),
+/// keeping each element's whole subtree and node ids. Works across documents.
+/// `index` is the position among the destination's files/groups (appended when None).
+#[tauri::command]
+async fn move_nodes(
+    node_ids: Vec<String>,
+    dest_id: String,
+    index: Option<usize>,
+) -> Result<(), String> {
+    transfer_nodes(node_ids, dest_id, index, false).await
+}
+
+/// Like move_nodes but inserts copies (with fresh node ids) and leaves the sources
+#[tauri::command]
+async fn copy_nodes(
+    node_ids: Vec<String>,
+    dest_id: String,
+    index: Option<usize>,
+) -> Result<(), String> {
+    transfer_nodes(node_ids, dest_id, index, true).await
+}
+
+async fn transfer_nodes(
+    node_ids: Vec<String>,
+    dest_id: String,
+    index: Option<usize>,
+    copy: bool,
+) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    ensure_known_ids(&cache, &node_ids)?;
+    ensure_known_ids(&cache, std::slice::from_ref(&dest_id))?;
+    let locate = |cache: &HashMap<String, CachedDoc>, id: &str| {
+        cache
+            .iter()
+            .find_map(|(p, e)| e.path_of(id).map(|np| (p.clone(), np.clone())))
+    };
+    let (dest_doc, dest_path) = locate(&cache, &dest_id).ok_or("destination not found")?;
+
+    // validate everything before touching any document
+    let mut sources: Vec<(String, String, NodePath)> = Vec::new();
+    for nid in node_ids.iter() {
+        let (doc, np) = locate(&cache, nid).ok_or("node not found")?;
+        match (np.kind(), dest_path.kind()) {
+            ("file", "group") | ("group", "group") | ("group", "target") => {}
+            (src, dst) => return Err(format!("cannot put a {} node into a {}", src, dst)),
+        }
+        if doc == dest_doc && (np == dest_path || dest_path.is_descendant_of(&np)) {
+            return Err(format!("cannot move {} into itself", np));
+        }
+        sources.push((nid.clone(), doc, np));
+    }
+    // a node selected together with one of its ancestors travels with the ancestor
+    let selected = sources.clone();
+    sources.retain(|(_, doc, np)| {
+        !selected
+            .iter()
+            .any(|(_, d, other)| d == doc && np.is_descendant_of(other))
+    });
+
+    // work on copies of the documents and put them in place only when the whole
+    // transfer succeeded; the destination is looked up by id since removing the
+    // sources shifts positional paths
+    let mut doms: HashMap<String, Element> = HashMap::new();
+    doms.insert(dest_doc.clone(), cache[&dest_doc].dom.clone());
+    let mut moved: Vec<Element> = Vec::new();
+    for (nid, doc, _) in sources.iter() {
+        let dom = doms
+            .entry(doc.clone())
+            .or_insert_with(|| cache[doc].dom.clone());
+        let (parent, pos) = find_parent_by_id_mut(dom, nid).ok_or("node not found")?;
+        if copy {
+            let xmltree::XMLNode::Element(el) = &parent.children[pos] else {
+                continue;
+            };
+            moved.push(strip_node_ids(el));
+        } else if let xmltree::XMLNode::Element(el) =
+            format::remove_child_at(&mut parent.children, pos)
+        {
+            moved.push(el);
+        }
+    }
+
+    let count = moved.len();
+    let moved_ids: Vec<String> = moved
+        .iter()
+        .filter_map(|el| node_id_of(el).map(|id| id.to_string()))
+        .collect();
+    let dest_dom = doms.get_mut(&dest_doc).ok_or("not loaded")?;
+    let dest_el = find_by_id_mut(dest_dom, &dest_id)
+        .ok_or_else(|| format!("node not found: {}", dest_path))?;
+    insert_tree_children(dest_el, moved, index);
+
+    // a move between documents keeps the node ids, so undoing only one side
+    // would leave a node in both documents (or in neither)
+    let link = (doms.len() > 1).then(|| EditLink {
+        id: Uuid::new_v4().to_string(),
+        paths: doms.keys().cloned().collect(),
+    });
+    let verb = if copy { "Copy" } else { "Move" };
+    for (doc, dom) in doms {
+        let entry = cache.get_mut(&doc).ok_or("not loaded")?;
+        if doc == dest_doc {
+            entry.reformat.extend(moved_ids.iter().cloned());
+        }
+        let before = std::mem::replace(&mut entry.dom, dom);
+        let label = format!("{} {} nodes", verb, count);
+        entry.finish_linked_edit(before, label, link.clone())?;
+    }
+    Ok(())
+}
+
+/// Insert file/group elements into `dest` (a group or target) at the given position
+/// among the existing children of the same kind, inside the <files>/<groups> wrapper
+fn insert_tree_children(dest: &mut Element, elements: Vec<Element>, index: Option<usize>) {
+    for (i, el) in elements.into_iter().enumerate() {
+        let wrapper = ensure_child_mut(dest, &format!("{}s", el.name));
+        let pos = index
+            .and_then(|idx| node_path::child_position(wrapper, &el.name, idx + i + 1))
+            .unwrap_or(wrapper.children.len());
+        wrapper.children.insert(pos, xmltree::XMLNode::Element(el));
+    }
+}
+
+/// Where reorder_node puts the node among its siblings
+#[derive(Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum ReorderTarget {
+    /// 0-based position among the sibling files (or groups); clamped to the end
+    Index {
+        index: usize,
+    },
+    Before {
+        sibling_id: String,
+    },
+    After {
+        sibling_id: String,
+    },
+}
+
+/// Move a file (or group) to another position inside its own <files> (<groups>).
+/// Only the elements are permuted: whitespace and comments between them stay in place.
+#[tauri::command]
+async fn reorder_node(node_id: String, target: ReorderTarget) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    let mut ids = vec![node_id.clone()];
+    if let ReorderTarget::Before { sibling_id } | ReorderTarget::After { sibling_id } = &target {
+        ids.push(sibling_id.clone());
+    }
+    ensure_known_ids(&cache, &ids)?;
+    let entry = cache
+        .values_mut()
+        .find(|e| e.path_of(&node_id).is_some())
+        .ok_or("node not found")?;
+    let node_path = entry.path_of(&node_id).cloned().ok_or("node not found")?;
+    if !matches!(node_path.kind(), "file" | "group") {
+        return Err(format!("cannot reorder a {} node", node_path.kind()));
+    }
+
+    let before = entry.dom.clone();
+    let (parent, _) = find_parent_by_id_mut(&mut entry.dom, &node_id).ok_or("node not found")?;
+    let kind = node_path.kind();
+    let slots: Vec<usize> = parent
+        .children
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| is_element_named(c, kind))
+        .map(|(i, _)| i)
+        .collect();
+    let mut order: Vec<xmltree::XMLNode> =
+        slots.iter().map(|&i| parent.children[i].clone()).collect();
+    let position_of = |order: &[xmltree::XMLNode], id: &str| {
+        order
+            .iter()
+            .position(|c| matches!(c, xmltree::XMLNode::Element(e) if node_id_of(e) == Some(id)))
+    };
+    let from = position_of(&order, &node_id).ok_or("node not found")?;
+    let moving = order.remove(from);
+    let to = match &target {
+        ReorderTarget::Index { index } => (*index).min(order.len()),
+        ReorderTarget::Before { sibling_id } | ReorderTarget::After { sibling_id } => {
+            let at = position_of(&order, sibling_id)
+                .ok_or_else(|| format!("{} is not a sibling of {}", sibling_id, node_id))?;
+            match target {
+                ReorderTarget::After { .. } => at + 1,
+                _ => at,
+            }
+        }
+    };
+    order.insert(to, moving);
+    for (slot, node) in slots.into_iter().zip(order) {
+        parent.children[slot] = node;
+    }
+    let label = format!("Move {} to position {}", node_path.kind(), to + 1);
+    entry.finish_edit(before, label)
+}
+
+/// Operation applied by bulk_edit to every element matched by the selector
+#[derive(Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+enum BulkOp {
+    /// replace the element's content with a text node
+    SetText {
+        text: String,
+    },
+    SetAttribute {
+        name: String,
+        value: String,
+    },
+    Remove,
+    /// replace the element with <name> containing it
+    Wrap {
+        name: String,
+    },
+    /// add <name>text</name> as the index-th child element (appended when index is None)
+    InsertChild {
+        name: String,
+        text: Option<String>,
+        index: Option<usize>,
+    },
+}
+
+impl BulkOp {
+    fn label(&self) -> &'static str {
+        match self {
+            BulkOp::SetText { .. } => "Set text",
+            BulkOp::SetAttribute { .. } => "Set attribute",
+            BulkOp::Remove => "Remove",
+            BulkOp::Wrap { .. } => "Wrap",
+            BulkOp::InsertChild { .. } => "Insert child",
+        }
+    }
+
+    /// Reject element and attribute names that would make the document malformed
+    /// or collide with the internal node id attribute
+    fn validate(&self) -> Result<(), String> {
+        let (kind, name) = match self {
+            BulkOp::SetAttribute { name, .. } => ("attribute", name),
+            BulkOp::Wrap { name } | BulkOp::InsertChild { name, .. } => ("element", name),
+            BulkOp::SetText { .. } | BulkOp::Remove => return Ok(()),
+        };
+        if !is_xml_name(name) {
+            return Err(format!("invalid {} name: {:?}", kind, name));
+        }
+        if name.starts_with("__") || name.starts_with("xmlns") {
+            return Err(format!("{} name {} is reserved", kind, name));
+        }
+        Ok(())
+    }
+
+    /// Apply to the element with node id `id`. Returns false if the element is
+    /// gone (e.g. an ancestor matched by the same selector was removed first).
+    fn apply(&self, root: &mut Element, id: &str) -> Result<bool, String> {
+        match self {
+            BulkOp::SetText { text } => {
+                let Some(el) = find_by_id_mut(root, id) else {
+                    return Ok(false);
+                };
+                el.children = vec![xmltree::XMLNode::Text(text.clone())];
+            }
+            BulkOp::SetAttribute { name, value } => {
+                let Some(el) = find_by_id_mut(root, id) else {
+                    return Ok(false);
+                };
+                el.attributes.insert(name.clone(), value.clone());
+            }
+            BulkOp::Remove => {
+                if node_id_of(root) == Some(id) {
+                    return Err("cannot remove the root element".into());
+                }
+                let Some((parent, pos)) = find_parent_by_id_mut(root, id) else {
+                    return Ok(false);
+                };
+                format::remove_child_at(&mut parent.children, pos);
+            }
+            BulkOp::Wrap { name } => {
+                if node_id_of(root) == Some(id) {
+                    return Err("cannot wrap the root element".into());
+                }
+                let Some((parent, pos)) = find_parent_by_id_mut(root, id) else {
+                    return Ok(false);
+                };
+                let mut wrapper = Element::new(name);
+                let inner = std::mem::replace(
+                    &mut parent.children[pos],
+                    xmltree::XMLNode::Text(String::new()),
+                );
+                wrapper.children.push(inner);
+                parent.children[pos] = xmltree::XMLNode::Element(wrapper);
+            }
+            BulkOp::InsertChild { name, text, index } => {
+                let Some(el) = find_by_id_mut(root, id) else {
+                    return Ok(false);
+                };
+                let mut child = Element::new(name);
+                if let Some(t) = text {
+                    child.children.push(xmltree::XMLNode::Text(t.clone()));
+                }
+                let pos = index
+                    .and_then(|i| {
+                        el.children
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, c)| matches!(c, xmltree::XMLNode::Element(_)))
+                            .map(|(p, _)| p)
+                            .nth(i)
+                    })
+                    .unwrap_or(el.children.len());
+                el.children.insert(pos, xmltree::XMLNode::Element(child));
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// An XML Name without namespace prefix (no ':'), so that it needs no declaration
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(is_name_start_char) && chars.all(is_name_char)
+}
+
+fn is_name_start_char(c: char) -> bool {
+    matches!(c,
+        'A'..='Z' | '_' | 'a'..='z'
+        | '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}' | '\u{F8}'..='\u{2FF}'
+        | '\u{370}'..='\u{37D}' | '\u{37F}'..='\u{1FFF}' | '\u{200C}'..='\u{200D}'
+        | '\u{2070}'..='\u{218F}' | '\u{2C00}'..='\u{2FEF}' | '\u{3001}'..='\u{D7FF}'
+        | '\u{F900}'..='\u{FDCF}' | '\u{FDF0}'..='\u{FFFD}' | '\u{10000}'..='\u{EFFFF}')
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start_char(c)
+        || matches!(c,
+            '-' | '.' | '0'..='9' | '\u{B7}' | '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}')
+}
+
+#[derive(Serialize)]
+struct BulkEditReport {
+    path: String,
+    changes: Vec<BulkEditChange>,
+}
+
+#[derive(Serialize)]
+struct BulkEditChange {
+    node_id: String,
+    element_path: NodePath,
+    before: String,
+    after: Option<String>, // None when the element was removed
+}
+
+/// Apply `op` to every element matched by the XPath `selector`, in the document
+/// `path` or in every loaded document when `path` is None.
+/// With `preview` nothing is changed; the returned report shows what would change.
+/// Each document gets a single undo entry.
+#[tauri::command]
+async fn bulk_edit(
+    path: Option<String>,
+    selector: String,
+    op: BulkOp,
+    preview: bool,
+) -> Result<Vec<BulkEditReport>, String> {
+    op.validate()?;
+    let mut cache = CACHE.lock().await;
+    let mut paths: Vec<String> = match &path {
+        Some(p) if !cache.contains_key(p) => return Err("not loaded".into()),
+        Some(p) => vec![p.clone()],
+        None => cache.keys().cloned().collect(),
+    };
+    paths.sort();
+
+    // select and apply on copies first so a failing document leaves every document untouched
+    let mut results = Vec::new();
+    for p in paths {
+        let entry = cache.get(&p).ok_or("not loaded")?;
+        let ids = evaluate_xpath(entry, &selector, |value, ids| match value {
+            Value::Nodeset(ns) => ns
+                .document_order()
+                .into_iter()
+                .map(|n| match n {
+                    sxd_xpath::nodeset::Node::Element(e) => ids
+                        .get(e)
+                        .map(|s| s.to_string())
+                        .ok_or_else(|| "matched element has no node id".to_string()),
+                    _ => Err(format!("selector must select elements: {}", selector)),
+                })
+                .collect::<Result<Vec<String>, String>>(),
+            _ => Err(format!("selector does not select nodes: {}", selector)),
+        })??;
+
+        let mut dom = entry.dom.clone();
+        let mut changes = Vec::new();
+        for id in ids {
+            let Some(element_path) = element_path(&dom, &id) else {
+                continue; // removed together with an ancestor
+            };
+            let before = serialize_element(
+                find_by_id(&dom, &id).ok_or("node not found")?,
+                &entry.format,
+            )?;
+            if op.apply(&mut dom, &id)? {
+                changes.push(BulkEditChange {
+                    node_id: id.clone(),
+                    element_path,
+                    before,
+                    after: match find_by_id(&dom, &id) {
+                        Some(el) => Some(serialize_element(el, &entry.format)?),
+                        None => None,
+                    },
+                });
+            }
+        }
+        results.push((p, dom, changes));
+    }
+
+    let mut reports = Vec::new();
+    for (p, dom, changes) in results {
+        if !preview && !changes.is_empty() {
+            let entry = cache.get_mut(&p).ok_or("not loaded")?;
+            let before = std::mem::replace(&mut entry.dom, dom);
+            let label = format!("{} on {} nodes", op.label(), changes.len());
+            entry.finish_edit(before, label)?;
+        }
+        reports.push(BulkEditReport { path: p, changes });
+    }
+    Ok(reports)
+}
+
+/// Revert the latest edit of a document. Node ids of the restored DOM are the
+/// ones it had before the edit, so previously held ids become valid again.
+/// An edit that changed several documents is reverted in all of them.
+#[tauri::command]
+async fn undo(path: String) -> Result<HistoryState, String> {
+    let mut cache = CACHE.lock().await;
+    step_history(&mut cache, &path, true)
+}
+
+/// Re-apply the latest undone edit of a document (in all documents it changed)
+#[tauri::command]
+async fn redo(path: String) -> Result<HistoryState, String> {
+    let mut cache = CACHE.lock().await;
+    step_history(&mut cache, &path, false)
+}
+
+/// Undo (or redo) the latest edit of `path`. A linked edit is stepped in every
+/// loaded document it changed, and only when it is the latest edit in each of them.
+fn step_history(
+    cache: &mut HashMap<String, CachedDoc>,
+    path: &str,
+    undo: bool,
+) -> Result<HistoryState, String> {
+    let entry = cache.get(path).ok_or("not loaded")?;
+    let last = entry.history.latest(undo).ok_or(if undo {
+        "nothing to undo"
+    } else {
+        "nothing to redo"
+    })?;
+    let mut paths = vec![path.to_string()];
+    if let Some(link) = &last.link {
+        for other in link.paths.iter().filter(|p| *p != path) {
+            let Some(e) = cache.get(other) else {
+                continue; // closed since
+            };
+            let linked = e
+                .history
+                .latest(undo)
+                .and_then(|h| h.link.as_ref())
+                .is_some_and(|l| l.id == link.id);
+            if !linked {
+                return Err(format!(
+                    "\"{}\" also changed {}, which has been edited since",
+                    last.label, other
+                ));
+            }
+            paths.push(other.clone());
+        }
+    }
+    for p in paths {
+        let e = cache.get_mut(&p).ok_or("not loaded")?;
+        let (from, to) = if undo {
+            (&mut e.history.undo, &mut e.history.redo)
+        } else {
+            (&mut e.history.redo, &mut e.history.undo)
+        };
+        let h = from.pop().ok_or("history changed")?;
+        let current = std::mem::replace(&mut e.dom, h.dom);
+        to.push(HistoryEntry {
+            label: h.label,
+            dom: current,
+            link: h.link,
+        });
+        e.change_count += 1;
+        e.reindex();
+        e.text = e.serialize()?;
+    }
+    let entry = cache.get(path).ok_or("not loaded")?;
+    Ok(entry.history_state(path))
+}
+
+/// Labels of the next undo/redo step, for the UI
+#[tauri::command]
+async fn get_history(path: String) -> Result<HistoryState, String> {
+    let cache = CACHE.lock().await;
+    let entry = cache.get(&path).ok_or("not loaded")?;
+    Ok(entry.history_state(&path))
+}
+
+/// Save cache for a given file path to disk
+/// - refuses with SaveError::Conflict when the file changed on disk since it was read
+///   (pass `force` to overwrite anyway)
+/// - writes to a temp file next to the target and renames it over the original
+/// - `backups` keeps that many rotated copies (file.xml.bak, file.xml.bak.1, ...)
+#[tauri::command]
+async fn save_file(
+    path: String,
+    force: Option<bool>,
+    backups: Option<usize>,
+) -> Result<(), SaveError> {
+    let mut cache = CACHE.lock().await;
+    let entry = cache
+        .get_mut(&path)
+        .ok_or_else(|| SaveError::NotLoaded { path: path.clone() })?;
+    let io_err = |e: std::io::Error| SaveError::Io {
+        path: path.clone(),
+        message: e.to_string(),
+    };
+
+    let on_disk = DiskState::read(&entry.path).map_err(io_err)?;
+    if !force.unwrap_or(false) {
+        match &on_disk {
+            Some(d) if d.hash == entry.disk.hash && d.len == entry.disk.len => {}
+            _ => {
+                return Err(SaveError::Conflict {
+                    path: path.clone(),
+                    missing: on_disk.is_none(),
+                    disk_modified: on_disk
+                        .as_ref()
+                        .and_then(|d| d.modified)
+                        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                        .map(|d| d.as_millis() as u64),
+                })
+            }
+        }
+    }
+
+    let keep = backups.unwrap_or(0);
+    if keep > 0 && on_disk.is_some() {
+        rotate_backups(&entry.path, keep).map_err(io_err)?;
+    }
+    // entry.text should be up-to-date
+    write_atomic(&entry.path, entry.text.as_bytes()).map_err(io_err)?;
+
+    entry.disk = DiskState::from_bytes(entry.text.as_bytes(), fs::metadata(&entry.path).ok());
+    entry.saved_dom = entry.dom.clone();
+    Ok(())
+}
+
+/// Write `bytes` to a temp file in the same directory and rename it over `path`,
+/// so a crash mid-write never leaves a truncated project file behind.
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dir.join(format!(".{}.{}.tmp", file_name, Uuid::new_v4()));
+    let result = (|| {
+        let mut f = fs::File::create(&tmp)?;
+        f.write_all(bytes)?;
+        f.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Copy the current file to `<file>.bak`, shifting older copies to `.bak.1`, `.bak.2`, ...
+/// and dropping anything beyond `keep` generations.
+fn rotate_backups(path: &Path, keep: usize) -> std::io::Result<()> {
+    let backup_name = |n: usize| {
+        let mut name = path.as_os_str().to_os_string();
+        if n == 0 {
+            name.push(".bak");
+        } else {
+            name.push(format!(".bak.{}", n));
+        }
+        PathBuf::from(name)
+    };
+    let oldest = backup_name(keep - 1);
+    if oldest.exists() {
+        fs::remove_file(&oldest)?;
+    }
+    for n in (0..keep - 1).rev() {
+        let from = backup_name(n);
+        if from.exists() {
+            fs::rename(&from, backup_name(n + 1))?;
+        }
+    }
+    fs::copy(path, backup_name(0))?;
+    Ok(())
+}
+
+/// List every loaded document with its unsaved-changes state
+#[tauri::command]
+async fn list_open_documents() -> Result<Vec<OpenDocument>, String> {
+    let cache = CACHE.lock().await;
+    Ok(open_documents(&cache))
+}
+
+fn open_documents(cache: &HashMap<String, CachedDoc>) -> Vec<OpenDocument> {
+    let mut docs: Vec<OpenDocument> = cache
+        .iter()
+        .map(|(path, entry)| OpenDocument {
+            path: path.clone(),
+            project_name: get_child_text(&entry.dom, "name"),
+            dirty: entry.is_dirty(),
+            change_count: entry.change_count,
+        })
+        .collect();
+    docs.sort_by(|a, b| a.path.cmp(&b.path));
+    docs
+}
+
+/// Close the window. Without `force`, refuses while any document has unsaved edits
+#[tauri::command]
+async fn close_window(window: tauri::Window, force: bool) -> Result<(), String> {
+    if !force {
+        let cache = CACHE.lock().await;
+        let dirty: Vec<String> = dirty_documents(&cache)
+            .into_iter()
+            .map(|d| d.path)
+            .collect();
+        if !dirty.is_empty() {
+            return Err(format!("unsaved changes: {}", dirty.join(", ")));
+        }
+    }
+    FORCE_CLOSE.store(true, Ordering::SeqCst);
+    window.close().map_err(|e| {
+        FORCE_CLOSE.store(false, Ordering::SeqCst);
+        format!("close err: {}", e)
+    })
 }
 
-/// Delete file nodes specified by node_ids (these should be file-level node ids)
-#[tauri::command]
-async fn delete_file_nodes(node_ids: Vec<String>) -> Result<(), String> {
-    let mut cache = CACHE.lock().await;
-    for (_path, entry) in cache.iter_mut() {
-        for nid in node_ids.iter() {
-            if let Some(xpath) = entry.node_map.get(nid) {
-                if !xpath.contains("/files/file[") {
-                    continue;
+/// Window close hook: keep the window open while unsaved edits exist and let the
+/// frontend decide (it receives "unsaved-changes" and may call close_window(force)).
+/// Runs on the event loop, so it never waits for the cache: while a command holds
+/// it, the close is postponed until the command is done and then decided there.
+fn on_close_requested(window: &tauri::Window, api: &tauri::CloseRequestApi) {
+    // a forced close applies to this one close request only
+    if FORCE_CLOSE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let Ok(cache) = CACHE.try_lock() else {
+        api.prevent_close();
+        let window = window.clone();
+        tauri::async_runtime::spawn(async move {
+            let dirty = dirty_documents(&*CACHE.lock().await);
+            if !dirty.is_empty() {
+                let _ = window.emit("unsaved-changes", dirty);
+            } else {
+                FORCE_CLOSE.store(true, Ordering::SeqCst);
+                if window.close().is_err() {
+                    FORCE_CLOSE.store(false, Ordering::SeqCst);
                 }
-                // remove the file element by adjusting parent's children
-                remove_file_by_xpath(&mut entry.dom, xpath)?;
             }
-        }
-        entry.text = serialize_element(&entry.dom)?;
+        });
+        return;
+    };
+    let dirty = dirty_documents(&cache);
+    if !dirty.is_empty() {
+        api.prevent_close();
+        let _ = window.emit("unsaved-changes", dirty);
     }
-    Ok(())
 }
 
-/// Save cache for a given file path to disk
-#[tauri::command]
-async fn save_file(path: String) -> Result<(), String> {
-    let mut cache = CACHE.lock().await;
-    let entry = cache.get_mut(&path).ok_or("not loaded")?;
-    // entry.text should be up-to-date
-    fs::write(&entry.path, &entry.text).map_err(|e| format!("write err: {}", e))?;
-    Ok(())
+fn dirty_documents(cache: &HashMap<String, CachedDoc>) -> Vec<OpenDocument> {
+    open_documents(cache)
+        .into_iter()
+        .filter(|d| d.dirty)
+        .collect()
 }
 
-fn serialize_element(el: &Element) -> Result<String, String> {
-    // write to memory (xmltree has write() but easier to use to_string)
-    let mut vec = Vec::new();
-    el.write(&mut vec)
-        .map_err(|e| format!("serialize err: {}", e))?;
-    String::from_utf8(vec).map_err(|e| format!("utf8 err: {}", e))
+/// Serialize the root element without node ids (no XML declaration; see DocFormat::render),
+/// empty elements in the form they had in the file and unedited tags and text
+/// spelled as in the file
+fn serialize_element(el: &Element, format: &DocFormat) -> Result<String, String> {
+    let body = format::write_body(&strip_node_ids(el), &format.empty_forms(el))?;
+    Ok(format.restore_spellings(&body))
 }
 
 /// Utility functions for xmltree traversal
@@ -333,22 +2451,12 @@ fn find_child_element<'a>(el: &'a Element, name: &str) -> Option<&'a Element> {
     None
 }
 
-/// get children elements with specific name (owned clones to avoid borrow issues)
-fn get_children_by_name(el: &Element, name: &str) -> Vec<Element> {
-    el.children
-        .iter()
-        .filter_map(|c| {
-            if let xmltree::XMLNode::Element(e) = c {
-                if e.name == name {
-                    Some(e.clone())
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        })
-        .collect()
+/// iterate children elements with specific name (borrowed)
+fn children_named<'a>(el: &'a Element, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+    el.children.iter().filter_map(move |c| match c {
+        xmltree::XMLNode::Element(e) if e.name == name => Some(e),
+        _ => None,
+    })
 }
 
 /// check xmltree::XMLNode is element with name
@@ -360,199 +2468,54 @@ fn is_element_named(node: &xmltree::XMLNode, name: &str) -> bool {
     }
 }
 
-/// Find the <files> Element and return (&mut files_el, Vec<Element> current file elements)
-/// xpath sample: /project/target[1]/groups/group[2]
-fn find_group_files_mut<'a>(
-    root: &'a mut Element,
-    xpath: &str,
-) -> Option<(&'a mut Element, Vec<Element>)> {
-    // parse xpath segments
-    // break by '/'
-    let segs: Vec<&str> = xpath.trim_matches('/').split('/').collect();
-    // we expect segs like ["project","target[1]","groups","group[2]"]
-    // we'll traverse from root
-    let mut cur: *mut Element = root as *mut _;
-    // for seg in segs.iter().skip(1) {
-
-    for seg in segs.iter().copied() {
-        // skip initial "project"
-        // parse name and optional [n]
-        let (name, idx) = if let Some(start) = seg.find('[') {
-            let name = &seg[0..start];
-            let idx_s = seg[start + 1..seg.len() - 1].to_string();
-            (name, idx_s.parse::<usize>().ok().unwrap_or(1))
-        } else {
-            (seg, 1usize)
-        };
-        // move cur to child element name with index idx (1-based)
-        unsafe {
-            let cur_ref: &mut Element = &mut *cur;
-            let mut count = 0usize;
-            let mut found_ptr: *mut Element = std::ptr::null_mut();
-            for child in cur_ref.children.iter_mut() {
-                if let xmltree::XMLNode::Element(e) = child {
-                    if e.name == name {
-                        count += 1;
-                        if count == idx {
-                            found_ptr = e as *mut _;
-                            break;
-                        }
-                    }
-                }
-            }
-            if found_ptr.is_null() {
-                return None;
-            }
-            cur = found_ptr;
-        }
-    }
-    // now cur points to the group element
-    unsafe {
-        let group_el: &mut Element = &mut *cur;
-        if let Some(files_child) = group_el.children.iter_mut().find_map(|c| {
-            if let xmltree::XMLNode::Element(e) = c {
-                if e.name == "files" {
-                    Some(e as *mut Element)
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        }) {
-            let files_el: &mut Element = &mut *files_child;
-            // collect file elements clones
-            let file_elems: Vec<Element> = files_el
-                .children
-                .iter()
-                .filter_map(|c| {
-                    if let xmltree::XMLNode::Element(e) = c {
-                        if e.name == "file" {
-                            Some(e.clone())
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-            return Some((files_el, file_elems));
-        } else {
-            // create one if not exists
-            let mut new_files = Element::new("files");
-            group_el.children.push(xmltree::XMLNode::Element(new_files));
-            // now find it again
-            if let Some(files_child) = group_el.children.iter_mut().find_map(|c| {
-                if let xmltree::XMLNode::Element(e) = c {
-                    if e.name == "files" {
-                        Some(e as *mut Element)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }) {
-                let files_el: &mut Element = &mut *files_child;
-                let file_elems: Vec<Element> = vec![];
-                return Some((files_el, file_elems));
-            }
-        }
-    }
-    None
+/// Find the <files> element of the group at `group_path`, creating it if missing
+fn group_files_mut<'a>(root: &'a mut Element, group_path: &NodePath) -> Option<&'a mut Element> {
+    let group_el = group_path.resolve_mut(root)?;
+    Some(ensure_child_mut(group_el, "files"))
 }
 
-/// Remove file by pseudo-xpath like ".../files/file[2]"
-fn remove_file_by_xpath(root: &mut Element, xpath: &str) -> Result<(), String> {
-    // find parent files element and index to remove
-    if let Some(pos) = xpath.rfind("/files/file[") {
-        let parent_path = &xpath[..pos + 7]; // includes /files
-                                             // parse index
-        if let Some(start) = xpath[pos + 12..].find(']') {
-            // actually simpler: extract last [n]
-        }
-    }
-    // simpler approach: traverse to files element and remove the indexed child
-    // get segments
-    let segs: Vec<&str> = xpath.trim_matches('/').split('/').collect();
-    // last segment is files/file[n], we want files element and index
-    if segs.len() < 1 {
-        return Err("invalid xpath".into());
-    }
-    let last = segs.last().unwrap();
-    // last should be like files/file[n] or file[n]
-    // find files element path by removing trailing /file[n]
-    let mut files_segs = segs.clone();
-    files_segs.pop(); // remove file[n]
-                      // now find files element
-    let mut cur: *mut Element = root as *mut _;
-    // for seg in files_segs.iter().skip(1) {
-    for seg in files_segs.iter().copied() {
-        // skip project
-        let (name, idx) = if let Some(start) = seg.find('[') {
-            let name = &seg[0..start];
-            let idx_s = seg[start + 1..seg.len() - 1].to_string();
-            (name, idx_s.parse::<usize>().ok().unwrap_or(1))
-        } else {
-            (seg, 1usize)
-        };
-        unsafe {
-            let cur_ref: &mut Element = &mut *cur;
-            let mut count = 0usize;
-            let mut found_ptr: *mut Element = std::ptr::null_mut();
-            for child in cur_ref.children.iter_mut() {
-                if let xmltree::XMLNode::Element(e) = child {
-                    if e.name == name {
-                        count += 1;
-                        if count == idx {
-                            found_ptr = e as *mut _;
-                            break;
-                        }
-                    }
-                }
-            }
-            if found_ptr.is_null() {
-                return Err("not found parent".into());
-            }
-            cur = found_ptr;
+/// First child element named `name`, appended (empty) if it does not exist yet
+fn ensure_child_mut<'a>(el: &'a mut Element, name: &str) -> &'a mut Element {
+    let pos = match node_path::child_position(el, name, 1) {
+        Some(pos) => pos,
+        None => {
+            el.children
+                .push(xmltree::XMLNode::Element(Element::new(name)));
+            el.children.len() - 1
         }
+    };
+    match &mut el.children[pos] {
+        xmltree::XMLNode::Element(e) => e,
+        _ => unreachable!("child_position only returns element positions"),
     }
+}
 
-    // now cur is files element
-    // parse file index from last seg
-    let seg = *segs.last().unwrap();
-    let idx = if let Some(start) = seg.find('[') {
-        seg[start + 1..seg.len() - 1]
-            .parse::<usize>()
-            .map_err(|_| "bad index")?
-    } else {
-        1usize
-    };
+/// `<tag><name>name</name></tag>`
+fn new_named_element(tag: &str, name: &str) -> Element {
+    let mut el = Element::new(tag);
+    el.children
+        .push(xmltree::XMLNode::Element(text_element("name", name)));
+    el
+}
 
-    unsafe {
-        let files_el: &mut Element = &mut *cur;
-        // find nth file element position among children
-        let mut count = 0usize;
-        let mut remove_pos: Option<usize> = None;
-        for (i, child) in files_el.children.iter().enumerate() {
-            if let xmltree::XMLNode::Element(e) = child {
-                if e.name == "file" {
-                    count += 1;
-                    if count == idx {
-                        remove_pos = Some(i);
-                        break;
-                    }
-                }
-            }
-        }
-        if let Some(pos) = remove_pos {
-            files_el.children.remove(pos);
-            return Ok(());
-        } else {
-            return Err("file not found".into());
-        }
-    }
+/// `<tag>text</tag>`
+fn text_element(tag: &str, text: &str) -> Element {
+    let mut el = Element::new(tag);
+    el.children.push(xmltree::XMLNode::Text(text.to_string()));
+    el
+}
+
+/// Look up the node id of the element addressed by an XPath positional path
+/// (e.g. `/project/target[1]/groups/group[2]`) in a loaded document
+#[tauri::command]
+async fn resolve_node_path(path: String, node_path: String) -> Result<String, String> {
+    let cache = CACHE.lock().await;
+    let entry = cache.get(&path).ok_or("not loaded")?;
+    let parsed = NodePath::parse(&node_path)?;
+    let el = parsed
+        .resolve(&entry.dom)
+        .ok_or_else(|| format!("no element at {}", parsed))?;
+    tree_key(el)
 }
 
 #[derive(Debug, Serialize)]
@@ -565,6 +2528,11 @@ struct TreeIncludeNode {
     selected: bool,
     inside_repo: bool,
     registed: HashMap<String, bool>,
+    /// subfolders on disk are listed in `children`; when false they are loaded
+    /// with get_include_children on expand
+    loaded: bool,
+    /// known to have no children (PrimeVue shows no expander)
+    leaf: bool,
 }
 
 /// 1. Gitリポジトリのルートを探す
@@ -583,6 +2551,80 @@ fn find_git_root(start: &Path) -> Option<PathBuf> {
     }
 }
 
+/// Folders git ignores in the repository at `repo_root` (untracked, ignored
+/// directories as listed by `git ls-files --others --ignored --directory`)
+fn git_ignored_dirs(repo_root: &Path) -> HashSet<PathBuf> {
+    let output = Command::new("git")
+        .args([
+            "ls-files",
+            "--others",
+            "--ignored",
+            "--exclude-standard",
+            "--directory",
+            "-z",
+        ])
+        .current_dir(repo_root)
+        .output();
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter_map(|p| p.strip_suffix('/'))
+            .map(|p| disk::normalize(&repo_root.join(p)))
+            .collect(),
+        _ => HashSet::new(),
+    }
+}
+
+/// Result of the git lookups for a project folder: the repository root and,
+/// once asked for, the folders git ignores there
+#[derive(Clone)]
+struct GitInfo {
+    root: Option<PathBuf>,
+    ignored: Option<Arc<HashSet<PathBuf>>>,
+}
+
+/// GitInfo per project folder, so that expanding a folder of the include tree
+/// does not run git again. get_include_tree_nodes refreshes the entry.
+static GIT_INFO: Lazy<std::sync::Mutex<HashMap<PathBuf, GitInfo>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// Repository root of `project_dir` (normalized) and the folders git ignores
+/// (empty unless `gitignore`), from GIT_INFO unless `refresh`.
+/// Runs git when not cached: call it off the async runtime.
+fn git_info(
+    project_dir: &Path,
+    gitignore: bool,
+    refresh: bool,
+) -> (Option<PathBuf>, Arc<HashSet<PathBuf>>) {
+    let cached = if refresh {
+        None
+    } else {
+        let cache = GIT_INFO.lock().unwrap_or_else(|e| e.into_inner());
+        cache.get(project_dir).cloned()
+    };
+    let mut info = cached.unwrap_or_else(|| GitInfo {
+        root: find_git_root(project_dir).map(|root| disk::normalize(&root)),
+        ignored: None,
+    });
+    if gitignore && info.ignored.is_none() {
+        let ignored = info
+            .root
+            .as_deref()
+            .map(git_ignored_dirs)
+            .unwrap_or_default();
+        info.ignored = Some(Arc::new(ignored));
+    }
+    let ignored = match &info.ignored {
+        Some(ignored) if gitignore => ignored.clone(),
+        _ => Default::default(),
+    };
+    GIT_INFO
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(project_dir.to_path_buf(), info.clone());
+    (info.root, ignored)
+}
+
 /// 3. 共通の親ディレクトリを探す（repo_root以下）
 fn common_parent<'a>(repo_root: &Path, paths: &[&'a Path]) -> PathBuf {
     let mut components: Vec<Vec<&std::ffi::OsStr>> = paths
@@ -619,25 +2661,51 @@ fn common_parent<'a>(repo_root: &Path, paths: &[&'a Path]) -> PathBuf {
     }
 }
 
-/// 4. 再帰的にフォルダツリーを走査（リポジトリ内）
-fn build_tree(base: &Path, repo_root: &Path, all_selected: &HashSet<PathBuf>) -> TreeIncludeNode {
+/// Include folders of a project and the configurations each one is registered in
+struct IncludeSelection {
+    all_selected: HashSet<PathBuf>,
+    registered: HashMap<PathBuf, HashSet<String>>,
+    /// configurations declared in the project
+    configurations: Vec<String>,
+}
+
+impl IncludeSelection {
+    fn registed(&self, path: &Path) -> HashMap<String, bool> {
+        let registered = self.registered.get(path);
+        self.configurations
+            .iter()
+            .map(|c| (c.clone(), registered.is_some_and(|r| r.contains(c))))
+            .collect()
+    }
+}
+
+/// 4. フォルダツリーを作成（リポジトリ内）
+///
+/// サブフォルダは `depth` 階層まで読み、それより深いものは get_include_children で読み込む
+fn build_tree(
+    base: &Path,
+    repo_root: &Path,
+    selection: &IncludeSelection,
+    scan: &disk::FolderScan,
+    depth: usize,
+) -> TreeIncludeNode {
+    let all_selected = &selection.all_selected;
     let exists = base.exists();
     let selected = all_selected.contains(base);
     let inside_repo = base.starts_with(repo_root);
+    let is_dir = exists && base.is_dir();
+    let loaded = !is_dir || depth > 0;
 
     let mut children = vec![];
 
-    // 1. 存在するディレクトリなら通常の fs::read_dir を走査
-    if exists && base.is_dir() {
-        if let Ok(entries) = fs::read_dir(base) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                children.push(build_tree(&path, repo_root, all_selected));
-            }
+    // 1. 存在するディレクトリなら除外されていないサブフォルダを走査
+    if is_dir && depth > 0 {
+        for path in scan.subfolders(base) {
+            children.push(build_tree(&path, repo_root, selection, scan, depth - 1));
         }
     }
 
-    // 2. 存在しない場合でも all_selected の中に base 以下のパスがある場合は子を生成
+    // 2. 存在しない場合や階層の制限を超える場合でも all_selected の中に base 以下のパスがある場合は子を生成
     for sel in all_selected.iter() {
         if sel.starts_with(base) && sel != base {
             let mut components = sel.strip_prefix(base).unwrap().components();
@@ -648,16 +2716,20 @@ fn build_tree(base: &Path, repo_root: &Path, all_selected: &HashSet<PathBuf>) ->
                     .iter()
                     .any(|c: &TreeIncludeNode| c.full_path == next_path.to_string_lossy())
                 {
-                    children.push(build_tree(&next_path, repo_root, all_selected));
+                    children.push(build_tree(
+                        &next_path,
+                        repo_root,
+                        selection,
+                        scan,
+                        depth.saturating_sub(1),
+                    ));
                 }
             }
         }
     }
 
-    let mut registed = HashMap::new();
-    registed.insert("arrayA".to_string(), false);
-    registed.insert("arrayB".to_string(), false);
-    registed.insert("arrayC".to_string(), false);
+    let registed = selection.registed(base);
+    let leaf = loaded && children.is_empty();
 
     TreeIncludeNode {
         id: base.to_string_lossy().to_string(),
@@ -675,76 +2747,554 @@ fn build_tree(base: &Path, repo_root: &Path, all_selected: &HashSet<PathBuf>) ->
         selected,
         inside_repo,
         registed,
+        loaded,
+        leaf,
     }
 }
 
 /// リポジトリ外ノードを単独で生成
-fn build_external_node(path: &Path, all_selected: &HashSet<PathBuf>) -> TreeIncludeNode {
-    let mut registed = HashMap::new();
-    registed.insert("arrayA".to_string(), false);
-    registed.insert("arrayB".to_string(), false);
-    registed.insert("arrayC".to_string(), false);
+fn build_external_node(path: &Path, selection: &IncludeSelection) -> TreeIncludeNode {
+    let registed = selection.registed(path);
     TreeIncludeNode {
         id: path.to_string_lossy().to_string(),
         label: path.to_string_lossy().to_string(), // ← repo外はフルパスをそのままlabelに
         full_path: path.to_string_lossy().to_string(),
         children: vec![],
         exists: path.exists(),
-        selected: all_selected.contains(path),
+        selected: selection.all_selected.contains(path),
         inside_repo: false,
         registed: registed,
+        loaded: true,
+        leaf: true,
+    }
+}
+
+/// Include directories: `<includes><include><path>..</path></include></includes>` under
+/// <project> (folders shown in the include tree) or under a <configuration> (folders
+/// registered in it). Paths are relative to the project file (or absolute).
+fn include_paths(root: &Element) -> Vec<String> {
+    children_named(root, "includes")
+        .flat_map(|includes| children_named(includes, "include"))
+        .filter_map(|include| get_child_text(include, "path"))
+        .map(|p| p.trim().to_string())
+        .collect()
+}
+
+/// `<configurations><configuration><name>..</name><includes>..</includes></configuration>`
+/// under <project>: (name, include paths) of each configuration
+fn configuration_includes(root: &Element) -> Vec<(String, Vec<String>)> {
+    children_named(root, "configurations")
+        .flat_map(|c| children_named(c, "configuration"))
+        .filter_map(|c| {
+            Some((
+                get_child_text(c, "name")?.trim().to_string(),
+                include_paths(c),
+            ))
+        })
+        .collect()
+}
+
+/// Names of the configurations declared in the project (include folders can be
+/// registered in these), in document order without repeats
+fn configuration_names(root: &Element) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for (name, _) in configuration_includes(root) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
     }
+    names
+}
+
+#[derive(Serialize)]
+struct ConfigurationIncludes {
+    name: String,
+    /// include paths as written in the project
+    paths: Vec<String>,
 }
 
+/// Include-path list of each configuration in the project `path` (loaded into the
+/// cache when it is not open yet, like get_include_tree_nodes)
 #[tauri::command]
-async fn get_include_tree_nodes() -> Result<Vec<TreeIncludeNode>, String> {
-    // 仮の入力
-    let known_file =
-        PathBuf::from("C:\\Users\\Admin\\Desktop\\script\\aaaa\\src-tauri\\.gitignore");
-    let given_folders = vec![
-        PathBuf::from("C:\\Users\\Admin\\Desktop\\script\\aaaa\\inc\\a\\b"),
-        PathBuf::from("C:\\Users\\Admin\\Desktop\\script\\aaaa\\inc\\g\\h"),
-        PathBuf::from("C:\\Users\\Admin\\Desktop\\script\\yamp-test\\src"),
-    ];
+async fn get_include_registrations(
+    path: String,
+) -> Result<Vec<ConfigurationIncludes>, IncludeTreeError> {
+    let mut cache = CACHE.lock().await;
+    let entry = include_document(&mut cache, &path)?;
+    let configured = configuration_includes(&entry.dom);
+    Ok(configuration_names(&entry.dom)
+        .into_iter()
+        .map(|name| ConfigurationIncludes {
+            paths: configured
+                .iter()
+                .filter(|(n, _)| *n == name)
+                .flat_map(|(_, paths)| paths.iter().cloned())
+                .collect(),
+            name,
+        })
+        .collect())
+}
 
-    // 1. gitリポジトリルート探索
-    let repo_root = find_git_root(&known_file.parent().unwrap()).expect("not in git repo");
+#[derive(Deserialize)]
+struct IncludeRegistration {
+    /// folder as shown in the include tree (TreeIncludeNode::full_path)
+    full_path: String,
+    configuration: String,
+    registered: bool,
+}
 
-    // 2. gitリポジトリ内だけを抽出
-    let inside: Vec<&Path> = given_folders
-        .iter()
-        .map(|p| p.as_path())
-        .filter(|p| p.starts_with(&repo_root))
-        .collect();
-    println!("inside:{:?}", inside);
-    let outside: Vec<&Path> = given_folders
+/// Register / unregister include folders in configurations of the project `path`
+/// (loaded into the cache when it is not open yet). The changes are one undoable
+/// edit; new paths are stored relative to the project file with the separator the
+/// project already uses.
+#[tauri::command]
+async fn set_include_registrations(
+    path: String,
+    changes: Vec<IncludeRegistration>,
+) -> Result<(), IncludeTreeError> {
+    let mut cache = CACHE.lock().await;
+    let entry = include_document(&mut cache, &path)?;
+    let invalid = |message: String| IncludeTreeError::Invalid {
+        path: path.clone(),
+        message,
+    };
+    let declared = configuration_names(&entry.dom);
+    if let Some(c) = changes
         .iter()
-        .map(|p| p.as_path())
-        .filter(|p| !p.starts_with(&repo_root))
-        .collect();
+        .find(|c| !declared.contains(&c.configuration))
+    {
+        return Err(invalid(format!(
+            "unknown configuration: {}",
+            c.configuration
+        )));
+    }
+    let project_dir = disk::normalize(entry.path.parent().unwrap_or(Path::new("")));
+    let mut written = include_paths(&entry.dom);
+    written.extend(
+        configuration_includes(&entry.dom)
+            .into_iter()
+            .flat_map(|(_, paths)| paths),
+    );
+    // resolve in the same order as get_include_tree_nodes, so that folders
+    // matched ignoring case map to the path shown in the tree
+    let mut resolver = disk::IncludeResolver::new(&project_dir);
+    for p in written.iter() {
+        resolver.resolve(p);
+    }
+    if written.is_empty() {
+        written = file_names(&entry.dom);
+    }
+    let sep = disk::detect_separator(written.iter().map(|s| s.as_str()));
+
+    let before = entry.dom.clone();
+    for change in changes.iter() {
+        let folder = resolver.resolve(&change.full_path);
+        let conf_el = configuration_mut(&mut entry.dom, &change.configuration)
+            .ok_or_else(|| invalid("configuration not found".to_string()))?;
+        let includes = ensure_child_mut(conf_el, "includes");
+        let pos = includes.children.iter().position(|c| match c {
+            xmltree::XMLNode::Element(e) if e.name == "include" => {
+                get_child_text(e, "path").is_some_and(|p| resolver.resolve(&p) == folder)
+            }
+            _ => false,
+        });
+        match (pos, change.registered) {
+            (None, true) => {
+                let mut include = Element::new("include");
+                let name = disk::name_for(&project_dir, &folder, sep).0;
+                include
+                    .children
+                    .push(xmltree::XMLNode::Element(text_element("path", &name)));
+                includes.children.push(xmltree::XMLNode::Element(include));
+            }
+            (Some(pos), false) => {
+                format::remove_child_at(&mut includes.children, pos);
+            }
+            _ => {}
+        }
+    }
+    entry
+        .finish_edit(before, "Update include registrations".to_string())
+        .map_err(invalid)
+}
+
+/// <configuration> with the given <name>
+fn configuration_mut<'a>(root: &'a mut Element, name: &str) -> Option<&'a mut Element> {
+    let (configurations, pos) = configuration_slot(root, name)?;
+    match &mut configurations.children[pos] {
+        xmltree::XMLNode::Element(e) => Some(e),
+        _ => None,
+    }
+}
+
+/// The <configurations> element holding the <configuration> with the given <name>,
+/// and its position among that element's children
+fn configuration_slot<'a>(root: &'a mut Element, name: &str) -> Option<(&'a mut Element, usize)> {
+    root.children
+        .iter_mut()
+        .filter_map(|c| match c {
+            xmltree::XMLNode::Element(e) if e.name == "configurations" => Some(e),
+            _ => None,
+        })
+        .find_map(|configurations| {
+            let pos = configurations.children.iter().position(|c| match c {
+                xmltree::XMLNode::Element(e) => {
+                    e.name == "configuration"
+                        && get_child_text(e, "name").is_some_and(|n| n.trim() == name)
+                }
+                _ => false,
+            })?;
+            Some((configurations, pos))
+        })
+}
+
+/// Name for a new configuration: trimmed, not empty and not declared yet
+fn new_configuration_name(root: &Element, name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("configuration name is empty".into());
+    }
+    if configuration_names(root).iter().any(|n| n == name) {
+        return Err(format!("configuration already exists: {}", name));
+    }
+    Ok(name.to_string())
+}
+
+/// Declare a configuration (without include folders) in the loaded document `path`
+#[tauri::command]
+async fn add_configuration(path: String, name: String) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    let entry = cache.get_mut(&path).ok_or("not loaded")?;
+    let name = new_configuration_name(&entry.dom, &name)?;
+    let before = entry.dom.clone();
+    ensure_child_mut(&mut entry.dom, "configurations")
+        .children
+        .push(xmltree::XMLNode::Element(new_named_element(
+            "configuration",
+            &name,
+        )));
+    entry.finish_edit(before, format!("Add configuration {}", name))
+}
 
-    // 3. 共通の親フォルダを探す
-    let common = common_parent(&repo_root, &inside);
+/// Rename a configuration; its include registrations stay with it
+#[tauri::command]
+async fn rename_configuration(path: String, name: String, new_name: String) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    let entry = cache.get_mut(&path).ok_or("not loaded")?;
+    let new_name = new_configuration_name(&entry.dom, &new_name)?;
+    let before = entry.dom.clone();
+    let conf_el = configuration_mut(&mut entry.dom, &name)
+        .ok_or_else(|| format!("unknown configuration: {}", name))?;
+    ensure_child_mut(conf_el, "name").children = vec![xmltree::XMLNode::Text(new_name.clone())];
+    entry.finish_edit(
+        before,
+        format!("Rename configuration {} to {}", name, new_name),
+    )
+}
+
+/// Copy a configuration with all its include registrations under a new name,
+/// right after the original
+#[tauri::command]
+async fn clone_configuration(path: String, name: String, new_name: String) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    let entry = cache.get_mut(&path).ok_or("not loaded")?;
+    let new_name = new_configuration_name(&entry.dom, &new_name)?;
+    let before = entry.dom.clone();
+    let (configurations, pos) = configuration_slot(&mut entry.dom, &name)
+        .ok_or_else(|| format!("unknown configuration: {}", name))?;
+    let xmltree::XMLNode::Element(original) = &configurations.children[pos] else {
+        return Err(format!("unknown configuration: {}", name));
+    };
+    // ids are assigned to the copy by finish_edit
+    let mut copy = strip_node_ids(original);
+    ensure_child_mut(&mut copy, "name").children = vec![xmltree::XMLNode::Text(new_name.clone())];
+    configurations
+        .children
+        .insert(pos + 1, xmltree::XMLNode::Element(copy));
+    entry.finish_edit(
+        before,
+        format!("Clone configuration {} as {}", name, new_name),
+    )
+}
+
+/// Delete a configuration together with its include registrations
+#[tauri::command]
+async fn delete_configuration(path: String, name: String) -> Result<(), String> {
+    let mut cache = CACHE.lock().await;
+    let entry = cache.get_mut(&path).ok_or("not loaded")?;
+    let before = entry.dom.clone();
+    let (configurations, pos) = configuration_slot(&mut entry.dom, &name)
+        .ok_or_else(|| format!("unknown configuration: {}", name))?;
+    format::remove_child_at(&mut configurations.children, pos);
+    entry.finish_edit(before, format!("Delete configuration {}", name))
+}
+
+/// Errors returned by the include tree commands. Serialized as
+/// `{ "kind": "...", ... }` like LoadError, which they wrap when the project
+/// file cannot be read.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum IncludeTreeError {
+    Load {
+        error: LoadError,
+    },
+    /// the request itself is invalid (e.g. a malformed exclude glob)
+    Invalid {
+        path: String,
+        message: String,
+    },
+    /// the folder scan did not finish (the background task panicked or was cancelled)
+    Scan {
+        path: String,
+        message: String,
+    },
+}
+
+impl From<LoadError> for IncludeTreeError {
+    fn from(error: LoadError) -> Self {
+        IncludeTreeError::Load { error }
+    }
+}
+
+/// What the include tree is built from: the project file and its include values
+struct IncludeSources {
+    project_file: PathBuf,
+    includes: Vec<String>,
+    configurations: Vec<(String, Vec<String>)>,
+    configuration_names: Vec<String>,
+}
 
-    // 4. ツリー作成
-    let all_selected: HashSet<PathBuf> = given_folders.iter().cloned().collect();
-    println!("all_selected:{:?}", all_selected);
-    let mut nodes = vec![];
+impl IncludeSources {
+    fn of(project_file: PathBuf, root: &Element) -> Self {
+        IncludeSources {
+            project_file,
+            includes: include_paths(root),
+            configurations: configuration_includes(root),
+            configuration_names: configuration_names(root),
+        }
+    }
 
-    if !inside.is_empty() {
-        let repo_tree = build_tree(&common, &repo_root, &all_selected);
-        nodes.push(repo_tree);
+    /// From the document `path` (loaded into the cache when it is not open yet)
+    async fn get(path: &str) -> Result<Self, LoadError> {
+        let mut cache = CACHE.lock().await;
+        let entry = include_document(&mut cache, path)?;
+        Ok(IncludeSources::of(entry.path.clone(), &entry.dom))
     }
-    println!("nodes:{:?}", nodes);
+}
 
-    // 追加: リポジトリ外ノード
-    for p in outside {
-        nodes.push(build_external_node(p, &all_selected));
+/// Cache entry of the project `path` for the include commands. A project file
+/// that is not open yet is loaded into the cache here, so that the tree and the
+/// registrations read (and edit) the same document.
+fn include_document<'a>(
+    cache: &'a mut HashMap<String, CachedDoc>,
+    path: &str,
+) -> Result<&'a mut CachedDoc, LoadError> {
+    match cache.entry(path.to_string()) {
+        std::collections::hash_map::Entry::Occupied(e) => Ok(e.into_mut()),
+        std::collections::hash_map::Entry::Vacant(e) => Ok(e.insert(load_file(path)?.0)),
     }
+}
 
-    // 5. JSONでPrimeVueに渡す（仮想ルートノード）
-    // println!("{}", serde_json::to_string_pretty(&nodes).unwrap());
-    Ok(nodes)
+#[derive(Deserialize, Debug)]
+struct IncludeScanOptions {
+    /// folder levels read below the tree root (or the expanded folder); deeper
+    /// folders are loaded on demand
+    #[serde(default = "default_scan_depth")]
+    depth: usize,
+    /// globs for folders that are not listed (patterns without '/' match the
+    /// folder name); disk::DEFAULT_FOLDER_EXCLUDES when not given
+    #[serde(default)]
+    exclude: Option<Vec<String>>,
+    /// also leave out folders ignored by git
+    #[serde(default = "default_gitignore")]
+    gitignore: bool,
+}
+
+fn default_scan_depth() -> usize {
+    1
+}
+
+fn default_gitignore() -> bool {
+    true
+}
+
+impl Default for IncludeScanOptions {
+    fn default() -> Self {
+        IncludeScanOptions {
+            depth: default_scan_depth(),
+            exclude: None,
+            gitignore: default_gitignore(),
+        }
+    }
+}
+
+/// Include folders of a project resolved against its repository
+struct IncludeContext {
+    repo_root: PathBuf,
+    given_folders: Vec<PathBuf>,
+    selection: IncludeSelection,
+    scan: disk::FolderScan,
+}
+
+impl IncludeContext {
+    /// Reads the disk and, unless cached in GIT_INFO (or `refresh`), runs git:
+    /// call it off the async runtime
+    fn new(
+        path: &str,
+        sources: IncludeSources,
+        options: &IncludeScanOptions,
+        refresh: bool,
+    ) -> Result<Self, IncludeTreeError> {
+        // 1. gitリポジトリルート探索（リポジトリ外ならプロジェクトのフォルダ）
+        let project_dir = disk::normalize(sources.project_file.parent().unwrap_or(Path::new("")));
+        let (git_root, ignored) = git_info(&project_dir, options.gitignore, refresh);
+        let repo_root = git_root.clone().unwrap_or_else(|| project_dir.clone());
+        // folders listed in <includes> and those registered in a configuration
+        let mut resolver = disk::IncludeResolver::new(&project_dir);
+        let mut given_folders: Vec<PathBuf> = Vec::new();
+        for p in sources.includes.iter() {
+            let folder = resolver.resolve(p);
+            if !given_folders.contains(&folder) {
+                given_folders.push(folder);
+            }
+        }
+        let mut registered: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+        for (name, paths) in sources.configurations.iter() {
+            for p in paths {
+                let folder = resolver.resolve(p);
+                if !given_folders.contains(&folder) {
+                    given_folders.push(folder.clone());
+                }
+                registered.entry(folder).or_default().insert(name.clone());
+            }
+        }
+
+        let exclude = match &options.exclude {
+            Some(globs) => globs.clone(),
+            None => disk::DEFAULT_FOLDER_EXCLUDES
+                .iter()
+                .map(|g| g.to_string())
+                .collect(),
+        };
+        let scan = disk::FolderScan::new(&repo_root, &exclude, ignored).map_err(|message| {
+            IncludeTreeError::Invalid {
+                path: path.to_string(),
+                message,
+            }
+        })?;
+
+        Ok(IncludeContext {
+            selection: IncludeSelection {
+                all_selected: given_folders.iter().cloned().collect(),
+                registered,
+                configurations: sources.configuration_names,
+            },
+            repo_root,
+            given_folders,
+            scan,
+        })
+    }
+}
+
+/// Run the include tree's git / filesystem work on the blocking thread pool, so
+/// that large folders do not hold up the async runtime
+async fn include_blocking<R: Send + 'static>(
+    path: &str,
+    f: impl FnOnce() -> Result<R, IncludeTreeError> + Send + 'static,
+) -> Result<R, IncludeTreeError> {
+    tauri::async_runtime::spawn_blocking(f)
+        .await
+        .map_err(|e| IncludeTreeError::Scan {
+            path: path.to_string(),
+            message: e.to_string(),
+        })?
+}
+
+/// Folder tree for the include directories of the project `path` (a loaded
+/// document, or a project file that is loaded into the cache). Includes inside the git
+/// repository containing the project (or the project's folder when it is not in
+/// a repository) share one tree rooted at their common parent; the others are
+/// listed as separate nodes. Subfolders are read `options.depth` levels deep
+/// (plus the way to every include folder); excluded and git-ignored folders are
+/// left out.
+#[tauri::command]
+async fn get_include_tree_nodes(
+    path: String,
+    options: Option<IncludeScanOptions>,
+) -> Result<Vec<TreeIncludeNode>, IncludeTreeError> {
+    let sources = IncludeSources::get(&path).await?;
+    let options = options.unwrap_or_default();
+    include_blocking(&path.clone(), move || {
+        // a new tree picks up changes to the repository and its .gitignore
+        let context = IncludeContext::new(&path, sources, &options, true)?;
+        let repo_root = &context.repo_root;
+
+        // 2. gitリポジトリ内だけを抽出
+        let inside: Vec<&Path> = context
+            .given_folders
+            .iter()
+            .map(|p| p.as_path())
+            .filter(|p| p.starts_with(repo_root))
+            .collect();
+        let outside: Vec<&Path> = context
+            .given_folders
+            .iter()
+            .map(|p| p.as_path())
+            .filter(|p| !p.starts_with(repo_root))
+            .collect();
+
+        // 3. 共通の親フォルダを探す
+        let common = common_parent(repo_root, &inside);
+
+        // 4. ツリー作成
+        let mut nodes = vec![];
+
+        if !inside.is_empty() {
+            let repo_tree = build_tree(
+                &common,
+                repo_root,
+                &context.selection,
+                &context.scan,
+                options.depth,
+            );
+            nodes.push(repo_tree);
+        }
+
+        // 追加: リポジトリ外ノード
+        for p in outside {
+            nodes.push(build_external_node(p, &context.selection));
+        }
+
+        // 5. JSONでPrimeVueに渡す（仮想ルートノード）
+        Ok(nodes)
+    })
+    .await
+}
+
+/// Subfolders of `folder` (a TreeIncludeNode::full_path) for the include tree of
+/// the project `path`, read `options.depth` levels deep; used when a folder whose
+/// children were not loaded yet is expanded
+#[tauri::command]
+async fn get_include_children(
+    path: String,
+    folder: String,
+    options: Option<IncludeScanOptions>,
+) -> Result<Vec<TreeIncludeNode>, IncludeTreeError> {
+    let sources = IncludeSources::get(&path).await?;
+    let options = options.unwrap_or_default();
+    include_blocking(&path.clone(), move || {
+        let context = IncludeContext::new(&path, sources, &options, false)?;
+        let folder = disk::normalize(Path::new(&folder));
+        let node = build_tree(
+            &folder,
+            &context.repo_root,
+            &context.selection,
+            &context.scan,
+            options.depth.max(1),
+        );
+        Ok(node.children)
+    })
+    .await
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -755,12 +3305,159 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             load_files,
             get_tree,
+            validate_files,
+            validate_schema,
             sort_groups,
+            find_duplicates,
+            dedupe,
             add_file_to_groups,
+            add_files_from_disk,
             delete_file_nodes,
+            bind_group_folder,
+            sync_group,
+            undo,
+            redo,
+            get_history,
+            resolve_node_path,
+            query_xpath,
+            bulk_edit,
+            add_group,
+            add_target,
+            rename_node,
+            delete_nodes,
+            duplicate_groups,
+            move_nodes,
+            copy_nodes,
+            reorder_node,
             get_include_tree_nodes,
-            save_file
+            get_include_children,
+            get_include_registrations,
+            set_include_registrations,
+            add_configuration,
+            rename_configuration,
+            clone_configuration,
+            delete_configuration,
+            save_file,
+            list_open_documents,
+            close_window
         ])
+        .on_window_event(|window, event| {
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                on_close_requested(window, api);
+            }
+        })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ids of the <group> elements in document order
+    fn group_ids(el: &Element, out: &mut Vec<String>) {
+        if el.name == "group" {
+            out.extend(node_id_of(el).map(|id| id.to_string()));
+        }
+        for child in el.children.iter() {
+            if let xmltree::XMLNode::Element(e) = child {
+                group_ids(e, out);
+            }
+        }
+    }
+
+    #[test]
+    fn include_registrations_load_a_project_that_is_not_open() {
+        let dir = std::env::temp_dir().join("aaaa_include_registrations");
+        fs::create_dir_all(dir.join("inc")).unwrap();
+        let path = dir.join("project.xml");
+        fs::write(
+            &path,
+            "<project><name>p</name><configurations><configuration>\
+             <name>Debug</name></configuration></configurations></project>",
+        )
+        .unwrap();
+        let path = path.to_string_lossy().into_owned();
+        tauri::async_runtime::block_on(async {
+            CACHE.lock().await.remove(&path);
+            let change = IncludeRegistration {
+                full_path: dir.join("inc").to_string_lossy().into_owned(),
+                configuration: "Debug".to_string(),
+                registered: true,
+            };
+            set_include_registrations(path.clone(), vec![change])
+                .await
+                .unwrap();
+            // the edit went to the cached document, which the next command reads
+            let configurations = get_include_registrations(path.clone()).await.unwrap();
+            assert_eq!(configurations.len(), 1);
+            assert_eq!(configurations[0].paths, ["inc"]);
+            assert_eq!(CACHE.lock().await[&path].history.undo.len(), 1);
+        });
+    }
+
+    #[test]
+    fn move_group_into_next_sibling() {
+        let path = std::env::temp_dir().join("aaaa_move_into_sibling.xml");
+        fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/../sample.xml"), &path).unwrap();
+        let path = path.to_string_lossy().into_owned();
+        tauri::async_runtime::block_on(async {
+            load_files(vec![path.clone()]).await.unwrap();
+            let mut groups = Vec::new();
+            group_ids(&CACHE.lock().await[&path].dom, &mut groups);
+            // the first group of tname1 into the second one
+            move_nodes(vec![groups[0].clone()], groups[1].clone(), None)
+                .await
+                .unwrap();
+            let cache = CACHE.lock().await;
+            let entry = &cache[&path];
+            let dest = entry.path_of(&groups[1]).unwrap();
+            assert_eq!(
+                entry.path_of(&groups[0]).unwrap().to_string(),
+                format!("{}/groups/group[1]", dest)
+            );
+            assert_eq!(entry.history.undo.len(), 1);
+            assert!(entry.text.contains("<name>fz</name>"));
+        });
+    }
+
+    #[test]
+    fn undo_move_between_documents_reverts_both() {
+        let mut paths = Vec::new();
+        for name in ["aaaa_move_from.xml", "aaaa_move_to.xml"] {
+            let path = std::env::temp_dir().join(name);
+            fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/../sample.xml"), &path).unwrap();
+            paths.push(path.to_string_lossy().into_owned());
+        }
+        let (from, to) = (&paths[0], &paths[1]);
+        tauri::async_runtime::block_on(async {
+            load_files(paths.clone()).await.unwrap();
+            let (mut from_groups, mut to_groups) = (Vec::new(), Vec::new());
+            group_ids(&CACHE.lock().await[from].dom, &mut from_groups);
+            group_ids(&CACHE.lock().await[to].dom, &mut to_groups);
+            let texts = |cache: &HashMap<String, CachedDoc>| {
+                (cache[from].text.clone(), cache[to].text.clone())
+            };
+            let original = texts(&*CACHE.lock().await);
+
+            move_nodes(vec![from_groups[0].clone()], to_groups[0].clone(), None)
+                .await
+                .unwrap();
+            let moved = texts(&*CACHE.lock().await);
+            undo(to.clone()).await.unwrap();
+            {
+                let cache = CACHE.lock().await;
+                assert_eq!(texts(&cache), original);
+                assert!(cache[from].path_of(&from_groups[0]).is_some());
+                assert!(cache[to].path_of(&from_groups[0]).is_none());
+            }
+            redo(from.clone()).await.unwrap();
+            assert_eq!(texts(&*CACHE.lock().await), moved);
+
+            // once one side is edited again, the move cannot be undone on the other alone
+            delete_nodes(vec![to_groups[1].clone()]).await.unwrap();
+            assert!(undo(from.clone()).await.is_err());
+            assert_eq!(texts(&*CACHE.lock().await).0, moved.0);
+        });
+    }
+}
@@ -0,0 +1,413 @@
+//! NodePath: typed absolute location path (XPath 1.0 abbreviated positional syntax)
+//! used to address project/target/group/file elements in the cached DOM.
+//!
+//! Accepted: `/project/target[1]/groups/group[2]`, `/project/target[position()=1]`,
+//! whitespace around tokens. A step without predicate means its first element.
+
+use serde::Serialize;
+use std::fmt;
+use xmltree::{Element, XMLNode};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Step {
+    pub(crate) name: String,
+    /// 1-based position among same-named siblings; None = written without predicate (first)
+    pub(crate) index: Option<usize>,
+}
+
+impl Step {
+    pub(crate) fn new(name: &str, index: usize) -> Self {
+        Step {
+            name: name.to_string(),
+            index: Some(index),
+        }
+    }
+
+    /// child wrapper step written without predicate, e.g. `groups`
+    pub(crate) fn first(name: &str) -> Self {
+        Step {
+            name: name.to_string(),
+            index: None,
+        }
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.index.unwrap_or(1)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct NodePath {
+    steps: Vec<Step>,
+}
+
+/// Parse error with the 1-based column where parsing failed
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct PathError {
+    pub(crate) input: String,
+    pub(crate) column: usize,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid path `{}` at column {}: {}",
+            self.input, self.column, self.message
+        )
+    }
+}
+
+impl From<PathError> for String {
+    fn from(e: PathError) -> String {
+        e.to_string()
+    }
+}
+
+impl NodePath {
+    pub(crate) fn root(name: &str) -> Self {
+        NodePath {
+            steps: vec![Step::first(name)],
+        }
+    }
+
+    pub(crate) fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub(crate) fn child(&self, step: Step) -> Self {
+        let mut steps = self.steps.clone();
+        steps.push(step);
+        NodePath { steps }
+    }
+
+    /// Append a relative path (as returned by tree_children)
+    pub(crate) fn join(&self, rel: &[Step]) -> Self {
+        let mut steps = self.steps.clone();
+        steps.extend(rel.iter().cloned());
+        NodePath { steps }
+    }
+
+    pub(crate) fn parent(&self) -> Option<NodePath> {
+        if self.steps.len() <= 1 {
+            return None;
+        }
+        Some(NodePath {
+            steps: self.steps[..self.steps.len() - 1].to_vec(),
+        })
+    }
+
+    pub(crate) fn last(&self) -> &Step {
+        self.steps.last().expect("NodePath always has a root step")
+    }
+
+    /// element name of the addressed node ("project", "target", "group", "file", ...)
+    pub(crate) fn kind(&self) -> &str {
+        &self.last().name
+    }
+
+    /// True when `self` lies strictly below `other`
+    pub(crate) fn is_descendant_of(&self, other: &NodePath) -> bool {
+        self.steps.len() > other.steps.len()
+            && self
+                .steps
+                .iter()
+                .zip(other.steps.iter())
+                .all(|(a, b)| a.name == b.name && a.position() == b.position())
+    }
+
+    pub(crate) fn parse(input: &str) -> Result<Self, PathError> {
+        Parser {
+            input,
+            chars: input.char_indices().collect(),
+            pos: 0,
+        }
+        .parse()
+    }
+
+    pub(crate) fn resolve<'a>(&self, root: &'a Element) -> Option<&'a Element> {
+        if root.name != self.steps[0].name {
+            return None;
+        }
+        let mut cur = root;
+        for step in &self.steps[1..] {
+            cur = nth_child(cur, &step.name, step.position())?;
+        }
+        Some(cur)
+    }
+
+    pub(crate) fn resolve_mut<'a>(&self, root: &'a mut Element) -> Option<&'a mut Element> {
+        if root.name != self.steps[0].name {
+            return None;
+        }
+        let mut cur = root;
+        for step in &self.steps[1..] {
+            cur = nth_child_mut(cur, &step.name, step.position())?;
+        }
+        Some(cur)
+    }
+}
+
+impl fmt::Display for NodePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            match step.index {
+                Some(i) => write!(f, "/{}[{}]", step.name, i)?,
+                None => write!(f, "/{}", step.name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for NodePath {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+/// n-th (1-based) child element with the given name
+pub(crate) fn nth_child<'a>(el: &'a Element, name: &str, n: usize) -> Option<&'a Element> {
+    el.children
+        .iter()
+        .filter_map(|c| match c {
+            XMLNode::Element(e) if e.name == name => Some(e),
+            _ => None,
+        })
+        .nth(n.checked_sub(1)?)
+}
+
+pub(crate) fn nth_child_mut<'a>(
+    el: &'a mut Element,
+    name: &str,
+    n: usize,
+) -> Option<&'a mut Element> {
+    el.children
+        .iter_mut()
+        .filter_map(|c| match c {
+            XMLNode::Element(e) if e.name == name => Some(e),
+            _ => None,
+        })
+        .nth(n.checked_sub(1)?)
+}
+
+/// index into `el.children` of the n-th (1-based) child element with the given name
+pub(crate) fn child_position(el: &Element, name: &str, n: usize) -> Option<usize> {
+    el.children
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| matches!(c, XMLNode::Element(e) if e.name == name))
+        .map(|(i, _)| i)
+        .nth(n.checked_sub(1)?)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: impl Into<String>) -> PathError {
+        PathError {
+            input: self.input.to_string(),
+            column: self.pos + 1,
+            message: message.into(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|(_, c)| *c)
+    }
+
+    fn skip_ws(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_ws();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        let save = self.pos;
+        for c in s.chars() {
+            if !self.eat(c) {
+                self.pos = save;
+                return false;
+            }
+        }
+        true
+    }
+
+    fn parse(mut self) -> Result<NodePath, PathError> {
+        let mut steps = Vec::new();
+        self.skip_ws();
+        if self.peek().is_none() {
+            return Err(self.error("empty path"));
+        }
+        if !self.eat('/') {
+            return Err(self.error("path must be absolute (start with '/')"));
+        }
+        loop {
+            if self.eat('/') {
+                self.pos -= 1;
+                return Err(self.error("'//' (descendant axis) is not a node address"));
+            }
+            steps.push(self.step()?);
+            self.skip_ws();
+            match self.peek() {
+                None => break,
+                Some('/') => {
+                    self.pos += 1;
+                }
+                Some(c) => return Err(self.error(format!("unexpected '{}'", c))),
+            }
+        }
+        Ok(NodePath { steps })
+    }
+
+    fn step(&mut self) -> Result<Step, PathError> {
+        let name = self.name()?;
+        if !self.eat('[') {
+            return Ok(Step { name, index: None });
+        }
+        self.skip_ws();
+        let index = if self.eat_str("position()") {
+            if !self.eat('=') {
+                return Err(self.error("expected '=' after position()"));
+            }
+            self.number()?
+        } else {
+            self.number()?
+        };
+        if !self.eat(']') {
+            return Err(match self.peek() {
+                None => self.error("expected ']' before end of path"),
+                Some(c) => self.error(format!("expected ']' but found '{}'", c)),
+            });
+        }
+        Ok(Step {
+            name,
+            index: Some(index),
+        })
+    }
+
+    fn name(&mut self) -> Result<String, PathError> {
+        self.skip_ws();
+        let start = self.pos;
+        match self.peek() {
+            Some(c) if c.is_alphabetic() || c == '_' => {}
+            None => return Err(self.error("expected element name before end of path")),
+            Some(c) => return Err(self.error(format!("expected element name but found '{}'", c))),
+        }
+        while self
+            .peek()
+            .is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            self.pos += 1;
+        }
+        let from = self.chars[start].0;
+        let to = self
+            .chars
+            .get(self.pos)
+            .map(|(i, _)| *i)
+            .unwrap_or(self.input.len());
+        Ok(self.input[from..to].to_string())
+    }
+
+    fn number(&mut self) -> Result<usize, PathError> {
+        self.skip_ws();
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(match self.peek() {
+                None => self.error("expected position before end of path"),
+                Some(c) => self.error(format!("expected position but found '{}'", c)),
+            });
+        }
+        let digits: String = self.chars[start..self.pos].iter().map(|(_, c)| c).collect();
+        match digits.parse::<usize>() {
+            Ok(0) => {
+                self.pos = start;
+                Err(self.error("positions are 1-based"))
+            }
+            Ok(n) => Ok(n),
+            Err(_) => {
+                self.pos = start;
+                Err(self.error("position out of range"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_at(input: &str) -> (usize, String) {
+        let e = NodePath::parse(input).unwrap_err();
+        (e.column, e.message)
+    }
+
+    #[test]
+    fn parse_accepts_positional_syntax() {
+        let path = NodePath::parse("/project/target[1]/groups/group[2]").unwrap();
+        assert_eq!(
+            path.steps(),
+            [
+                Step::first("project"),
+                Step::new("target", 1),
+                Step::first("groups"),
+                Step::new("group", 2),
+            ]
+        );
+        assert_eq!(path.to_string(), "/project/target[1]/groups/group[2]");
+        assert_eq!(NodePath::parse(&path.to_string()).unwrap(), path);
+
+        let spaced = NodePath::parse(" / project / target [ position() = 2 ] ").unwrap();
+        assert_eq!(spaced.to_string(), "/project/target[2]");
+    }
+
+    #[test]
+    fn parse_reports_the_failing_column() {
+        assert_eq!(
+            error_at("/project/target[1"),
+            (18, "expected ']' before end of path".into())
+        );
+        assert_eq!(
+            error_at("/project/target[0]"),
+            (17, "positions are 1-based".into())
+        );
+        assert_eq!(
+            error_at("//file"),
+            (2, "'//' (descendant axis) is not a node address".into())
+        );
+        assert_eq!(
+            error_at("/project/"),
+            (10, "expected element name before end of path".into())
+        );
+        assert_eq!(
+            error_at("/project/target[1]]"),
+            (19, "unexpected ']'".into())
+        );
+        assert_eq!(
+            error_at("/project/target[position()2]"),
+            (27, "expected '=' after position()".into())
+        );
+        assert_eq!(
+            error_at("project"),
+            (1, "path must be absolute (start with '/')".into())
+        );
+    }
+}
@@ -0,0 +1,551 @@
+//! Project schema: which children each element may have and how many.
+//! The built-in rules describe project / target / group / file; they can be
+//! replaced by a rules file (JSON form of `Schema`) or by an XSD (common subset:
+//! xs:element, named and anonymous xs:complexType, xs:sequence / xs:choice /
+//! xs:all, minOccurs / maxOccurs, ref, xs:any, simple types as text).
+
+use crate::{format, node_id_of, NODE_ID_ATTR};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use xmltree::{Element, XMLNode};
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct Schema {
+    root: ChildRule,
+    /// rules by type name; a ChildRule without `type` uses its element name
+    types: HashMap<String, ElementRule>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct ElementRule {
+    #[serde(default)]
+    children: Vec<ChildRule>,
+    /// text-only element such as <name>
+    #[serde(default)]
+    text: bool,
+    /// children not listed in `children` are allowed
+    #[serde(default)]
+    open: bool,
+    /// sets of children that should not be used side by side (e.g. <target> and <targets>)
+    #[serde(default)]
+    exclusive: Vec<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ChildRule {
+    name: String,
+    #[serde(rename = "type")]
+    type_name: Option<String>,
+    #[serde(default)]
+    min: usize,
+    /// None = unbounded
+    max: Option<usize>,
+}
+
+impl ChildRule {
+    fn new(name: &str, min: usize, max: Option<usize>) -> Self {
+        ChildRule {
+            name: name.to_string(),
+            type_name: None,
+            min,
+            max,
+        }
+    }
+
+    fn type_name(&self) -> &str {
+        self.type_name.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+}
+
+/// One schema violation; line/column are filled in by `locate`
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct Diagnostic {
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+    pub(crate) node_id: Option<String>,
+    /// 1-based position of the element's start tag in the document text
+    pub(crate) line: Option<u64>,
+    pub(crate) column: Option<u64>,
+}
+
+impl Schema {
+    /// Rules for the project files this app edits
+    pub(crate) fn builtin() -> Self {
+        let many = |name: &str| ChildRule::new(name, 0, None);
+        let one = |name: &str| ChildRule::new(name, 1, Some(1));
+        let optional = |name: &str| ChildRule::new(name, 0, Some(1));
+        let rule = |children: Vec<ChildRule>, exclusive: &[&[&str]]| ElementRule {
+            children,
+            exclusive: exclusive
+                .iter()
+                .map(|set| set.iter().map(|s| s.to_string()).collect())
+                .collect(),
+            ..Default::default()
+        };
+        let text = ElementRule {
+            text: true,
+            ..Default::default()
+        };
+        let types = HashMap::from([
+            (
+                "project".to_string(),
+                rule(
+                    vec![
+                        one("name"),
+                        optional("includes"),
+                        optional("configurations"),
+                        many("targets"),
+                        many("target"),
+                    ],
+                    &[&["targets", "target"]],
+                ),
+            ),
+            ("includes".to_string(), rule(vec![many("include")], &[])),
+            ("include".to_string(), rule(vec![one("path")], &[])),
+            (
+                "configurations".to_string(),
+                rule(vec![many("configuration")], &[]),
+            ),
+            (
+                "configuration".to_string(),
+                rule(vec![one("name"), optional("includes")], &[]),
+            ),
+            ("targets".to_string(), rule(vec![many("target")], &[])),
+            (
+                "target".to_string(),
+                rule(
+                    vec![
+                        one("name"),
+                        many("targets"),
+                        many("target"),
+                        many("groups"),
+                        many("group"),
+                    ],
+                    &[&["targets", "target"], &["groups", "group"]],
+                ),
+            ),
+            ("groups".to_string(), rule(vec![many("group")], &[])),
+            (
+                "group".to_string(),
+                rule(
+                    vec![
+                        one("name"),
+                        optional("sync"),
+                        many("groups"),
+                        many("group"),
+                        many("files"),
+                        many("file"),
+                    ],
+                    &[&["groups", "group"], &["files", "file"]],
+                ),
+            ),
+            ("files".to_string(), rule(vec![many("file")], &[])),
+            ("file".to_string(), rule(vec![one("name")], &[])),
+            (
+                "sync".to_string(),
+                rule(vec![one("folder"), many("pattern")], &[]),
+            ),
+            ("name".to_string(), text.clone()),
+            ("folder".to_string(), text.clone()),
+            ("pattern".to_string(), text.clone()),
+            ("path".to_string(), text),
+        ]);
+        Schema {
+            root: one("project"),
+            types,
+        }
+    }
+
+    /// Rules from a file: `.xsd` is read as XML Schema, anything else as JSON rules
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("read error: {}", e))?;
+        let is_xsd = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("xsd"));
+        if is_xsd {
+            let root =
+                Element::parse(text.as_bytes()).map_err(|e| format!("xsd parse error: {}", e))?;
+            from_xsd(&root)
+        } else {
+            serde_json::from_str(&text).map_err(|e| format!("schema rules error: {}", e))
+        }
+    }
+
+    /// Check `root` against the rules. Positions are not set yet (see `locate`).
+    pub(crate) fn validate(&self, root: &Element) -> Vec<Diagnostic> {
+        let mut out = Vec::new();
+        if root.name != self.root.name {
+            out.push(diagnostic(
+                Severity::Error,
+                format!(
+                    "root element must be <{}>, found <{}>",
+                    self.root.name, root.name
+                ),
+                root,
+            ));
+            return out;
+        }
+        self.check(root, self.root.type_name(), &mut out);
+        out
+    }
+
+    fn check(&self, el: &Element, type_name: &str, out: &mut Vec<Diagnostic>) {
+        // elements without a rule are not constrained
+        let Some(rule) = self.types.get(type_name) else {
+            return;
+        };
+        let elements: Vec<&Element> = el
+            .children
+            .iter()
+            .filter_map(|c| match c {
+                XMLNode::Element(e) => Some(e),
+                _ => None,
+            })
+            .collect();
+        if rule.text {
+            for child in elements {
+                out.push(diagnostic(
+                    Severity::Error,
+                    format!(
+                        "<{}> must contain text only, found <{}>",
+                        el.name, child.name
+                    ),
+                    child,
+                ));
+            }
+            return;
+        }
+        if el
+            .children
+            .iter()
+            .any(|c| matches!(c, XMLNode::Text(t) if !t.trim().is_empty()))
+        {
+            out.push(diagnostic(
+                Severity::Warning,
+                format!("unexpected text in <{}>", el.name),
+                el,
+            ));
+        }
+
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for child in elements.iter() {
+            let n = counts.entry(&child.name).or_insert(0);
+            *n += 1;
+            match rule.children.iter().find(|c| c.name == child.name) {
+                Some(c) => {
+                    if c.max.is_some_and(|max| *n > max) {
+                        out.push(diagnostic(
+                            Severity::Error,
+                            format!(
+                                "<{}> allows at most {} <{}>",
+                                el.name,
+                                c.max.unwrap_or_default(),
+                                child.name
+                            ),
+                            child,
+                        ));
+                    }
+                    self.check(child, c.type_name(), out);
+                }
+                None if rule.open => {}
+                None => out.push(diagnostic(
+                    Severity::Error,
+                    format!("<{}> is not allowed in <{}>", child.name, el.name),
+                    child,
+                )),
+            }
+        }
+        for c in rule.children.iter() {
+            let n = counts.get(c.name.as_str()).copied().unwrap_or(0);
+            if n < c.min {
+                let message = if c.min == 1 {
+                    format!("<{}> is missing required <{}>", el.name, c.name)
+                } else {
+                    format!(
+                        "<{}> needs at least {} <{}>, found {}",
+                        el.name, c.min, c.name, n
+                    )
+                };
+                out.push(diagnostic(Severity::Error, message, el));
+            }
+        }
+        for set in rule.exclusive.iter() {
+            let used: Vec<&String> = set
+                .iter()
+                .filter(|s| counts.contains_key(s.as_str()))
+                .collect();
+            if used.len() > 1 {
+                let names: Vec<String> = used.iter().map(|s| format!("<{}>", s)).collect();
+                out.push(diagnostic(
+                    Severity::Warning,
+                    format!("<{}> mixes {}", el.name, names.join(" and ")),
+                    el,
+                ));
+            }
+        }
+    }
+}
+
+fn diagnostic(severity: Severity, message: String, el: &Element) -> Diagnostic {
+    Diagnostic {
+        severity,
+        message,
+        node_id: node_id_of(el).map(|s| s.to_string()),
+        line: None,
+        column: None,
+    }
+}
+
+/// Fill in line/column of each diagnostic from `text`, the serialized form of
+/// `root` (elements appear in the same document order in both). The position is
+/// that of the element's `<`.
+pub(crate) fn locate(diagnostics: &mut [Diagnostic], root: &Element, text: &str) {
+    fn ids<'a>(el: &'a Element, out: &mut Vec<Option<&'a str>>) {
+        out.push(el.attributes.get(NODE_ID_ATTR).map(|s| s.as_str()));
+        for c in el.children.iter() {
+            if let XMLNode::Element(e) = c {
+                ids(e, out);
+            }
+        }
+    }
+    let mut order = Vec::new();
+    ids(root, &mut order);
+    let mut positions: HashMap<&str, (u64, u64)> = HashMap::new();
+    // line and start offset of the line of the previous tag, counted on from there
+    let (mut line, mut line_start, mut scanned) = (1, 0, 0);
+    for (id, (range, _)) in order.into_iter().zip(format::start_tags(text)) {
+        for (i, c) in text[scanned..range.start].char_indices() {
+            if c == '\n' {
+                line += 1;
+                line_start = scanned + i + 1;
+            }
+        }
+        scanned = range.start;
+        if let Some(id) = id {
+            let column = text[line_start..range.start].chars().count() as u64 + 1;
+            positions.insert(id, (line, column));
+        }
+    }
+    for d in diagnostics.iter_mut() {
+        if let Some(&(line, column)) = d.node_id.as_deref().and_then(|id| positions.get(id)) {
+            d.line = Some(line);
+            d.column = Some(column);
+        }
+    }
+}
+
+/// Build rules from an XSD (see the module comment for the supported subset)
+fn from_xsd(xsd: &Element) -> Result<Schema, String> {
+    let mut builder = XsdBuilder {
+        xsd,
+        types: HashMap::new(),
+        anonymous: 0,
+    };
+    let root_decl = xsd_children(xsd, "element")
+        .next()
+        .ok_or("xsd has no top-level xs:element")?;
+    let root = builder.element(root_decl, 1, Some(1))?;
+    Ok(Schema {
+        root,
+        types: builder.types,
+    })
+}
+
+struct XsdBuilder<'a> {
+    xsd: &'a Element,
+    types: HashMap<String, ElementRule>,
+    anonymous: usize,
+}
+
+impl XsdBuilder<'_> {
+    /// ChildRule for an xs:element declaration, registering its type
+    fn element(
+        &mut self,
+        decl: &Element,
+        min: usize,
+        max: Option<usize>,
+    ) -> Result<ChildRule, String> {
+        if let Some(r) = decl.attributes.get("ref") {
+            let target = xsd_children(self.xsd, "element")
+                .find(|e| e.attributes.get("name").map(|n| n.as_str()) == Some(local(r)))
+                .ok_or_else(|| format!("xsd: unknown element ref `{}`", r))?;
+            return self.element(target, min, max);
+        }
+        let name = decl
+            .attributes
+            .get("name")
+            .ok_or("xsd: xs:element without name or ref")?
+            .clone();
+        let type_name = match decl.attributes.get("type") {
+            Some(t) if is_builtin_type(t) => {
+                let key = format!("{}#text", name);
+                self.types.insert(
+                    key.clone(),
+                    ElementRule {
+                        text: true,
+                        ..Default::default()
+                    },
+                );
+                key
+            }
+            Some(t) => {
+                let t = local(t).to_string();
+                if !self.types.contains_key(&t) {
+                    let def = xsd_children(self.xsd, "complexType")
+                        .chain(xsd_children(self.xsd, "simpleType"))
+                        .find(|e| e.attributes.get("name") == Some(&t))
+                        .ok_or_else(|| format!("xsd: unknown type `{}`", t))?;
+                    // placeholder first so recursive types terminate
+                    self.types.insert(t.clone(), ElementRule::default());
+                    let rule = self.type_rule(def)?;
+                    self.types.insert(t.clone(), rule);
+                }
+                t
+            }
+            None => {
+                self.anonymous += 1;
+                let key = format!("{}#{}", name, self.anonymous);
+                let rule = match xsd_children(decl, "complexType")
+                    .chain(xsd_children(decl, "simpleType"))
+                    .next()
+                {
+                    Some(def) => self.type_rule(def)?,
+                    // no type at all: xs:anyType
+                    None => ElementRule {
+                        open: true,
+                        ..Default::default()
+                    },
+                };
+                self.types.insert(key.clone(), rule);
+                key
+            }
+        };
+        Ok(ChildRule {
+            name,
+            type_name: Some(type_name),
+            min,
+            max,
+        })
+    }
+
+    fn type_rule(&mut self, def: &Element) -> Result<ElementRule, String> {
+        if def.name == "simpleType"
+            || def.attributes.get("mixed").is_some_and(|m| m == "true")
+            || xsd_children(def, "simpleContent").next().is_some()
+        {
+            return Ok(ElementRule {
+                text: true,
+                ..Default::default()
+            });
+        }
+        let mut rule = ElementRule::default();
+        for particle in def.children.iter().filter_map(|c| match c {
+            XMLNode::Element(e) => Some(e),
+            _ => None,
+        }) {
+            self.particle(particle, 1, Some(1), false, &mut rule)?;
+        }
+        Ok(rule)
+    }
+
+    /// Flatten xs:sequence / xs:choice / xs:all into per-child counts (order is not checked)
+    fn particle(
+        &mut self,
+        p: &Element,
+        outer_min: usize,
+        outer_max: Option<usize>,
+        in_choice: bool,
+        rule: &mut ElementRule,
+    ) -> Result<(), String> {
+        let (min, max) = occurs(p)?;
+        let min = if in_choice { 0 } else { min * outer_min };
+        let max = match (max, outer_max) {
+            (Some(a), Some(b)) => Some(a * b),
+            _ => None,
+        };
+        match p.name.as_str() {
+            "element" => {
+                let child = self.element(p, min, max)?;
+                rule.children.push(child);
+            }
+            "any" => rule.open = true,
+            "sequence" | "all" | "choice" => {
+                let choice = p.name == "choice";
+                for c in p.children.iter() {
+                    if let XMLNode::Element(e) = c {
+                        self.particle(e, min, max, in_choice || choice, rule)?;
+                    }
+                }
+            }
+            // attributes and annotations do not constrain child elements
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn xsd_children<'a>(el: &'a Element, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+    el.children.iter().filter_map(move |c| match c {
+        XMLNode::Element(e) if e.name == name => Some(e),
+        _ => None,
+    })
+}
+
+/// minOccurs / maxOccurs of a particle (both default to 1)
+fn occurs(p: &Element) -> Result<(usize, Option<usize>), String> {
+    let parse = |v: &str| {
+        v.parse::<usize>()
+            .map_err(|_| format!("xsd: invalid occurrence `{}`", v))
+    };
+    let min = match p.attributes.get("minOccurs") {
+        Some(v) => parse(v)?,
+        None => 1,
+    };
+    let max = match p.attributes.get("maxOccurs").map(|s| s.as_str()) {
+        Some("unbounded") => None,
+        Some(v) => Some(parse(v)?),
+        None => Some(1),
+    };
+    Ok((min, max))
+}
+
+/// `xs:string` -> `string`
+fn local(qname: &str) -> &str {
+    qname.rsplit(':').next().unwrap_or(qname)
+}
+
+fn is_builtin_type(t: &str) -> bool {
+    t.starts_with("xs:") || t.starts_with("xsd:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locate_points_at_the_start_tag() {
+        let text = "<project>\n  <x/>\n  <y\n     a=\"1\">é</y><z/>\n</project>\n";
+        let mut root = crate::format::parse_preserving(text).unwrap();
+        crate::assign_node_ids(&mut root);
+        let mut elements = Vec::new();
+        crate::format::preorder(&root, &mut elements);
+        let mut diagnostics: Vec<Diagnostic> = elements
+            .iter()
+            .map(|el| diagnostic(Severity::Error, el.name.clone(), el))
+            .collect();
+        locate(&mut diagnostics, &root, text);
+        let positions: Vec<(u64, u64)> = diagnostics
+            .iter()
+            .map(|d| (d.line.unwrap(), d.column.unwrap()))
+            .collect();
+        assert_eq!(positions, [(1, 1), (2, 3), (3, 3), (4, 17)]);
+    }
+}
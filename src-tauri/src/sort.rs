@@ -0,0 +1,135 @@
+//! Sort orders for sort_groups
+
+use serde::Deserialize;
+use std::cmp::Ordering;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SortMode {
+    /// plain byte-wise comparison of <name> (previous behaviour)
+    #[default]
+    Byte,
+    /// digit runs compare as numbers: f2 < f10
+    Natural,
+    /// like Natural, ignoring case
+    CaseInsensitive,
+    /// by file extension, then by name
+    Extension,
+    /// by directory part, then by basename
+    Path,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct SortOptions {
+    #[serde(default)]
+    pub(crate) mode: SortMode,
+    #[serde(default = "default_true")]
+    pub(crate) ascending: bool,
+    /// file names (full <name> or basename) kept at the top in their current order
+    #[serde(default)]
+    pub(crate) pinned: Vec<String>,
+    /// also reorder nested groups by their <name> (same mode and direction as files)
+    #[serde(default)]
+    pub(crate) group_names: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl SortOptions {
+    pub(crate) fn is_pinned(&self, name: &str) -> bool {
+        self.pinned
+            .iter()
+            .any(|p| p == name || p == split_path(name).1)
+    }
+
+    /// Order of two file names: pinned files first (ties keep their current
+    /// order, since callers use a stable sort), then `compare`
+    pub(crate) fn compare_files(&self, a: &str, b: &str) -> Ordering {
+        match (self.is_pinned(a), self.is_pinned(b)) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => self.compare(a, b),
+        }
+    }
+
+    pub(crate) fn compare(&self, a: &str, b: &str) -> Ordering {
+        let ord = match self.mode {
+            SortMode::Byte => a.cmp(b),
+            SortMode::Natural => natural_cmp(a, b),
+            SortMode::CaseInsensitive => {
+                natural_cmp(&a.to_lowercase(), &b.to_lowercase()).then_with(|| natural_cmp(a, b))
+            }
+            SortMode::Extension => {
+                let ext = |s: &str| {
+                    let base = split_path(s).1;
+                    base.rfind('.')
+                        .map(|i| base[i + 1..].to_lowercase())
+                        .unwrap_or_default()
+                };
+                ext(a).cmp(&ext(b)).then_with(|| natural_cmp(a, b))
+            }
+            SortMode::Path => {
+                let (da, ba) = split_path(a);
+                let (db, bb) = split_path(b);
+                natural_cmp(&normalize_separators(da), &normalize_separators(db))
+                    .then_with(|| natural_cmp(ba, bb))
+            }
+        };
+        if self.ascending {
+            ord
+        } else {
+            ord.reverse()
+        }
+    }
+}
+
+/// ("dir/sub", "file.c") for both '/' and '\' separators; dir is "" for a bare name
+fn split_path(name: &str) -> (&str, &str) {
+    match name.rfind(['/', '\\']) {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => ("", name),
+    }
+}
+
+fn normalize_separators(s: &str) -> String {
+    s.replace('\\', "/")
+}
+
+/// Compare strings treating runs of ASCII digits as numbers ("f2" < "f10").
+/// Equal numbers with different zero padding fall back to the shorter run first.
+pub(crate) fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ca), Some(cb)) if ca.is_ascii_digit() && cb.is_ascii_digit() => {
+                let na = a.len() - a.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                let nb = b.len() - b.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                let (da, db) = (&a[..na], &b[..nb]);
+                let (ta, tb) = (da.trim_start_matches('0'), db.trim_start_matches('0'));
+                let ord = ta
+                    .len()
+                    .cmp(&tb.len())
+                    .then_with(|| ta.cmp(tb))
+                    .then_with(|| da.len().cmp(&db.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a = &a[na..];
+                b = &b[nb..];
+            }
+            (Some(ca), Some(cb)) => {
+                if ca != cb {
+                    return ca.cmp(&cb);
+                }
+                a = &a[ca.len_utf8()..];
+                b = &b[cb.len_utf8()..];
+            }
+        }
+    }
+}